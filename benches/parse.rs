@@ -0,0 +1,34 @@
+//! Times `parse_file` on flat and deeply nested inputs that double in size,
+//! to check the time per megabyte stays flat. Run with `cargo bench`.
+
+use std::time::Instant;
+
+use frospy::parser::{parse_file, FileId};
+
+fn flat(n: usize) -> String {
+    "($x ^x 1 + [1 2.5 \"str\"] {'k #\\a}) ; comment\n".repeat(n)
+}
+
+fn nested(n: usize) -> String {
+    format!("{}x{}", "(".repeat(n), ")".repeat(n))
+}
+
+fn main() {
+    for (name, make) in [("flat", flat as fn(usize) -> String), ("nested", nested)] {
+        for shift in 0..6 {
+            let src = make(20_000 << shift);
+            let start = Instant::now();
+            let (exprs, diagnostics) = parse_file(FileId::default(), &src);
+            let elapsed = start.elapsed();
+            assert!(diagnostics.is_empty());
+
+            let mb = src.len() as f64 / 1e6;
+            println!(
+                "{name:>6} {mb:>8.2} MB {:>10.1?} {:>8.1} ms/MB",
+                elapsed,
+                elapsed.as_secs_f64() * 1e3 / mb
+            );
+            drop(exprs);
+        }
+    }
+}
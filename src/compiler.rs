@@ -18,6 +18,13 @@ fn compile_exprs_to_f(exprs: &[Expr], name: String) -> String {
 
         match e {
             Expr::Integer(i, _) => code.push_str(&format!("stack.push(Value::Integer({}));", i)),
+            Expr::Float(x, _) => code.push_str(&format!(
+                "stack.push(Value::Float(f64::from_bits({:#x})));",
+                x.to_bits()
+            )),
+            Expr::String(s, _) => {
+                code.push_str(&format!("stack.push(Value::String({:?}.to_string()));", s))
+            }
             Expr::Atom(a, _) => match a.as_str() {
                 "quote" => {
                     let qe;
@@ -28,18 +35,27 @@ fn compile_exprs_to_f(exprs: &[Expr], name: String) -> String {
                         Expr::Integer(i, _) => {
                             code.push_str(&format!("stack.push(Value::Integer({}));", i))
                         }
-                        Expr::Atom(a, _) => code
-                            .push_str(&format!("stack.push(Value::Atom(\"{}\".to_string()));", a)),
+                        Expr::Float(x, _) => code.push_str(&format!(
+                            "stack.push(Value::Float(f64::from_bits({:#x})));",
+                            x.to_bits()
+                        )),
+                        Expr::Atom(a, _) => {
+                            code.push_str(&format!("stack.push(Value::Atom({:?}.to_string()));", a))
+                        }
+                        Expr::String(s, _) => code
+                            .push_str(&format!("stack.push(Value::String({:?}.to_string()));", s)),
                         Expr::Thunk(_, _) => panic!("Can't quote a thunk"),
+                        e => panic!("Can't quote {e}"),
                     }
                 }
                 a => {
                     code.push_str(&format!(
                         "{{
-let t = env.get(\"{}\").expect(\"Unbound var {}\").clone();
+let t = env.get({:?}).expect({:?}).clone();
 call_value(&mut env, stack, t);
 }}",
-                        a, a,
+                        a,
+                        format!("Unbound var {a}"),
                     ));
                 }
             },
@@ -54,6 +70,7 @@ call_value(&mut env, stack, t);
                     "stack.push(Value::Thunk {{ env: env.clone(), fp: {name} }});"
                 ));
             }
+            e => panic!("{e} isn't supported by this compiler"),
         }
     }
 
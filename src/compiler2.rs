@@ -4,13 +4,16 @@ use itertools::Itertools;
 
 use crate::{
     cps::{self, ExprCPS},
-    parser::Expr,
+    eval::{EvalError, EvalLimits, EvalStacktrace},
+    parser::{self, Expr, Span},
+    source_map::SourceMap,
     util,
 };
 
 // Push and pop are reserved primitives
 
 pub const HEADER: &str = include_str!("./header/header.rs");
+pub const BIGINT: &str = include_str!("./header/bigint.rs");
 
 fn eprint_expr_cps_ref(tag: &str, exprs: &[ExprCPSRef]) {
     eprint!("{tag} [");
@@ -20,62 +23,249 @@ fn eprint_expr_cps_ref(tag: &str, exprs: &[ExprCPSRef]) {
     eprintln!("]");
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExprCPSRef {
-    IntegerLiteral(i64),
-    AtomLiteral(String),
-    ThunkRef(String),
-    ForceByCC,     // Pops CC first, then the thunk to force
-    ForceByCCBare, // Pops CC, forces CC
+    IntegerLiteral(i64, Span),
+    FloatLiteral(f64, Span),
+    AtomLiteral(String, Span),
+    StringLiteral(String, Span),
+    CharLiteral(char, Span),
+    BytesLiteral(Vec<u8>, Span),
+    Quotation(Vec<ExprCPSRef>, String, Span), // Elements and the thunk to run
+    ListLiteral(Vec<ExprCPSRef>, Span),
+    MapLiteral(Vec<ExprCPSRef>, Span), // Alternating keys and values
+    ThunkRef(String, Span),
+    ListStart(Span),
+    ListEnd(Span),
+    MapEnd(Span),
+    ForceByCC(Span),     // Pops CC first, then the thunk to force
+    ForceByCCBare(Span), // Pops CC, forces CC
     Terminate,
-    Push,
-    Pop,
+    Push(Span),
+    Pop(Span),
 }
 
+impl ExprCPSRef {
+    /// The elements of a literal that has them.
+    fn items(&self) -> Option<&[ExprCPSRef]> {
+        match self {
+            ExprCPSRef::Quotation(es, _, _)
+            | ExprCPSRef::ListLiteral(es, _)
+            | ExprCPSRef::MapLiteral(es, _) => Some(es),
+            _ => None,
+        }
+    }
+}
+
+/// Quoted literals nest as deeply as the source does, so dropping one
+/// doesn't recurse.
+impl Drop for ExprCPSRef {
+    fn drop(&mut self) {
+        let take = |e: &mut ExprCPSRef| match e {
+            ExprCPSRef::Quotation(es, _, _)
+            | ExprCPSRef::ListLiteral(es, _)
+            | ExprCPSRef::MapLiteral(es, _) => std::mem::take(es),
+            _ => vec![],
+        };
+        let mut todo = take(self);
+        while let Some(mut e) = todo.pop() {
+            todo.append(&mut take(&mut e));
+        }
+    }
+}
+
+/// Nor does printing one.
 impl Display for ExprCPSRef {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ExprCPSRef::IntegerLiteral(i) => f.write_fmt(format_args!("{}", i)),
-            ExprCPSRef::AtomLiteral(a) => f.write_fmt(format_args!("'{}", a)),
-            ExprCPSRef::ThunkRef(tr) => f.write_fmt(format_args!("&{tr}")),
-            ExprCPSRef::ForceByCC => f.write_fmt(format_args!("-forceCC")),
-            ExprCPSRef::ForceByCCBare => f.write_fmt(format_args!("-forceCCbare")),
-            ExprCPSRef::Terminate => f.write_fmt(format_args!("-terminate")),
-            ExprCPSRef::Pop => f.write_fmt(format_args!("-pop")),
-            ExprCPSRef::Push => f.write_fmt(format_args!("-push")),
+        enum Todo<'a> {
+            Expr(&'a ExprCPSRef),
+            Str(&'a str),
         }
+
+        let mut todo = vec![Todo::Expr(self)];
+        while let Some(t) = todo.pop() {
+            let e = match t {
+                Todo::Expr(e) => e,
+                Todo::Str(s) => {
+                    f.write_str(s)?;
+                    continue;
+                }
+            };
+            let (open, es, close): (_, &[ExprCPSRef], _) = match e {
+                ExprCPSRef::IntegerLiteral(i, _) => {
+                    f.write_fmt(format_args!("{}", i))?;
+                    continue;
+                }
+                ExprCPSRef::FloatLiteral(x, _) => {
+                    f.write_str(&parser::float_literal(*x))?;
+                    continue;
+                }
+                ExprCPSRef::AtomLiteral(a, _) => {
+                    f.write_fmt(format_args!("'{}", a))?;
+                    continue;
+                }
+                ExprCPSRef::StringLiteral(s, _) => {
+                    f.write_fmt(format_args!("{:?}", s))?;
+                    continue;
+                }
+                ExprCPSRef::CharLiteral(c, _) => {
+                    f.write_str(&parser::char_literal(*c))?;
+                    continue;
+                }
+                ExprCPSRef::BytesLiteral(b, _) => {
+                    f.write_str(&parser::bytes_literal(b))?;
+                    continue;
+                }
+                ExprCPSRef::ThunkRef(tr, _) => {
+                    f.write_fmt(format_args!("&{tr}"))?;
+                    continue;
+                }
+                ExprCPSRef::Quotation(es, tr, _) => {
+                    todo.push(Todo::Str(tr));
+                    ("'( ", es, ")&")
+                }
+                ExprCPSRef::ListLiteral(es, _) => ("'[ ", es, "]"),
+                ExprCPSRef::MapLiteral(es, _) => ("'{ ", es, "}"),
+                ExprCPSRef::ListStart(_) => ("-list-start", &[], ""),
+                ExprCPSRef::ListEnd(_) => ("-list-end", &[], ""),
+                ExprCPSRef::MapEnd(_) => ("-map-end", &[], ""),
+                ExprCPSRef::ForceByCC(_) => ("-forceCC", &[], ""),
+                ExprCPSRef::ForceByCCBare(_) => ("-forceCCbare", &[], ""),
+                ExprCPSRef::Terminate => ("-terminate", &[], ""),
+                ExprCPSRef::Pop(_) => ("-pop", &[], ""),
+                ExprCPSRef::Push(_) => ("-push", &[], ""),
+            };
+            f.write_str(open)?;
+            todo.push(Todo::Str(close));
+            for e in es.iter().rev() {
+                todo.push(Todo::Str(" "));
+                todo.push(Todo::Expr(e));
+            }
+        }
+        Ok(())
     }
 }
 
 pub type CPSProgram = HashMap<String, Vec<ExprCPSRef>>;
 
+/// Every thunk becomes an entry of its own, named in its parent. Entries
+/// wait on a list to be lowered, since thunks can nest as deeply as the
+/// program is long, and quoted literals are lowered on a stack of their
+/// own.
 pub fn expr_cps_to_program(exprs: &[ExprCPS]) -> CPSProgram {
-    fn internal(prog: &mut CPSProgram, name: String, exprs: &[ExprCPS]) {
-        let v = exprs
-            .iter()
-            .map(|e| match e {
-                ExprCPS::IntegerLiteral(i, _) => ExprCPSRef::IntegerLiteral(*i),
-                ExprCPS::AtomLiteral(a, _) => ExprCPSRef::AtomLiteral(a.to_string()),
-                ExprCPS::Thunk(vec, _) => {
+    struct Lowering<'a> {
+        pending: Vec<(String, &'a [ExprCPS])>,
+        /// Thunk names by span, and whether the thunk's code has been
+        /// seen. A quotation nested in another has none of its own, and
+        /// shares the entry of the thunk with its span in the outer one's.
+        names: HashMap<&'a Span, (String, bool)>,
+    }
+
+    impl<'a> Lowering<'a> {
+        fn thunk(&mut self, exprs: &'a [ExprCPS], s: &'a Span) -> String {
+            let name = match self.names.get_mut(s) {
+                Some((name, seen @ false)) => {
+                    *seen = true;
+                    name.clone()
+                }
+                // Lists and forces have thunks too, which may share a span
+                _ => {
                     let name = util::random_name();
-                    internal(prog, name.to_string(), vec);
-                    ExprCPSRef::ThunkRef(name.to_string())
+                    self.names.entry(s).or_insert((name.clone(), true));
+                    name
                 }
-                ExprCPS::ForceCC(_) => ExprCPSRef::ForceByCC,
-                ExprCPS::Terminate => ExprCPSRef::Terminate,
-                ExprCPS::Pop(_) => ExprCPSRef::Pop,
-                ExprCPS::Push(_) => ExprCPSRef::Push,
-                ExprCPS::Force(_) => panic!("Force without CC not possible here"),
-                ExprCPS::ForceCCBare(_) => ExprCPSRef::ForceByCCBare,
-            })
-            .collect();
+            };
+            self.pending.push((name.clone(), exprs));
+            name
+        }
 
-        prog.insert(name, v);
+        fn quoted(&mut self, s: &'a Span) -> String {
+            let (name, _) = self
+                .names
+                .entry(s)
+                .or_insert_with(|| (util::random_name(), false));
+            name.clone()
+        }
+
+        fn lower(&mut self, e: &'a ExprCPS) -> ExprCPSRef {
+            enum Todo<'a> {
+                Lower(&'a ExprCPS),
+                /// Takes its elements off the end of `done`
+                Assemble(&'a ExprCPS, usize),
+            }
+
+            let mut todo = vec![Todo::Lower(e)];
+            let mut done = vec![];
+            while let Some(t) = todo.pop() {
+                let lowered = match t {
+                    Todo::Lower(e) => match e {
+                        ExprCPS::IntegerLiteral(i, s) => ExprCPSRef::IntegerLiteral(*i, s.clone()),
+                        ExprCPS::FloatLiteral(x, s) => ExprCPSRef::FloatLiteral(*x, s.clone()),
+                        ExprCPS::AtomLiteral(a, s) => {
+                            ExprCPSRef::AtomLiteral(a.to_string(), s.clone())
+                        }
+                        ExprCPS::StringLiteral(st, s) => {
+                            ExprCPSRef::StringLiteral(st.to_string(), s.clone())
+                        }
+                        ExprCPS::CharLiteral(c, s) => ExprCPSRef::CharLiteral(*c, s.clone()),
+                        ExprCPS::BytesLiteral(b, s) => {
+                            ExprCPSRef::BytesLiteral(b.clone(), s.clone())
+                        }
+                        ExprCPS::Quotation(items, _, _)
+                        | ExprCPS::ListLiteral(items, _)
+                        | ExprCPS::MapLiteral(items, _) => {
+                            todo.push(Todo::Assemble(e, items.len()));
+                            todo.extend(items.iter().rev().map(Todo::Lower));
+                            continue;
+                        }
+                        ExprCPS::ListStart(s) => ExprCPSRef::ListStart(s.clone()),
+                        ExprCPS::ListEnd(s) => ExprCPSRef::ListEnd(s.clone()),
+                        ExprCPS::MapEnd(s) => ExprCPSRef::MapEnd(s.clone()),
+                        ExprCPS::Thunk(vec, s) => {
+                            ExprCPSRef::ThunkRef(self.thunk(vec, s), s.clone())
+                        }
+                        ExprCPS::ForceCC(s) => ExprCPSRef::ForceByCC(s.clone()),
+                        ExprCPS::Terminate => ExprCPSRef::Terminate,
+                        ExprCPS::Pop(s) => ExprCPSRef::Pop(s.clone()),
+                        ExprCPS::Push(s) => ExprCPSRef::Push(s.clone()),
+                        ExprCPS::Force(_) => panic!("Force without CC not possible here"),
+                        ExprCPS::ForceCCBare(s) => ExprCPSRef::ForceByCCBare(s.clone()),
+                    },
+                    Todo::Assemble(e, n) => {
+                        let items = done.split_off(done.len() - n);
+                        match e {
+                            ExprCPS::Quotation(_, Some(thunk), s) => match thunk.as_ref() {
+                                ExprCPS::Thunk(vec, ts) => {
+                                    ExprCPSRef::Quotation(items, self.thunk(vec, ts), s.clone())
+                                }
+                                _ => panic!("Quotation without a thunk"),
+                            },
+                            ExprCPS::Quotation(_, None, s) => {
+                                ExprCPSRef::Quotation(items, self.quoted(s), s.clone())
+                            }
+                            ExprCPS::ListLiteral(_, s) => ExprCPSRef::ListLiteral(items, s.clone()),
+                            ExprCPS::MapLiteral(_, s) => ExprCPSRef::MapLiteral(items, s.clone()),
+                            _ => unreachable!("Only literals have elements"),
+                        }
+                    }
+                };
+                done.push(lowered);
+            }
+            done.pop().unwrap()
+        }
     }
 
+    let mut l = Lowering {
+        pending: vec![("entry".to_string(), exprs)],
+        names: HashMap::new(),
+    };
     let mut prog = HashMap::new();
 
-    internal(&mut prog, "entry".to_string(), exprs);
+    while let Some((name, exprs)) = l.pending.pop() {
+        let v = exprs.iter().map(|e| l.lower(e)).collect();
+        prog.insert(name, v);
+    }
 
     prog
 }
@@ -83,6 +273,7 @@ pub fn expr_cps_to_program(exprs: &[ExprCPS]) -> CPSProgram {
 pub fn main_function() -> String {
     r#"
 fn main() {
+    std::panic::set_hook(Box::new(report_panic));
     println!("Hello, world!");
     let mut stack = Vec::new();
     let mut env = make_env();
@@ -103,14 +294,80 @@ fn make_thunk_ref_enum(prog: &CPSProgram) -> String {
     code
 }
 
-fn compile_toplevel(prog: &CPSProgram, opts: &CompilerOptions) -> String {
+/// Where in the source each instruction that can fail came from, which
+/// becomes the `FILES` and `SPANS` tables of the generated program. Equal
+/// locations share an entry, so the tables stay small.
+struct SpanTable<'a> {
+    sources: &'a SourceMap,
+    /// File, line and column
+    locations: Vec<(usize, usize, usize)>,
+    indices: HashMap<(usize, usize, usize), usize>,
+}
+
+impl<'a> SpanTable<'a> {
+    fn new(sources: &'a SourceMap) -> Self {
+        SpanTable {
+            sources,
+            locations: vec![],
+            indices: HashMap::new(),
+        }
+    }
+
+    /// The entry for `span`, unless the compiler made it up.
+    fn index(&mut self, span: &Span) -> Option<usize> {
+        if *span == parser::dummy_span() {
+            return None;
+        }
+        let l = self.sources.location(span)?;
+        let key = (span.file.0, l.line, l.col);
+        let next = self.locations.len();
+        Some(*self.indices.entry(key).or_insert_with(|| {
+            self.locations.push(key);
+            next
+        }))
+    }
+
+    fn code(&self) -> String {
+        let files = self
+            .sources
+            .files()
+            .map(|f| format!("{:?}", self.sources.get(f).unwrap().name))
+            .join(",");
+        let locations = self
+            .locations
+            .iter()
+            .map(|(f, l, c)| format!("({f},{l},{c})"))
+            .join(",");
+        format!(
+            "pub static FILES: &[&str] = &[{files}];\
+             pub static SPANS: &[(u32, u32, u32)] = &[{locations}];"
+        )
+    }
+}
+
+fn compile_toplevel(prog: &CPSProgram, spans: &mut SpanTable, opts: &CompilerOptions) -> String {
     let mut code = String::new();
     code.push_str("fn top_level(env: &mut Env, stack: &mut Stack) {");
 
     code.push_str("let mut cur_frame =  Frame{tr: ThunkRef::entry, env: env.clone()};");
 
+    // Stack heights where the list and map literals being built start
+    code.push_str("let mut marks: Vec<usize> = vec![];");
+
+    if opts.limits.fuel.is_some() {
+        code.push_str("let mut steps: usize = 0;");
+    }
+
     code.push_str("loop {");
 
+    if let Some(max) = opts.limits.fuel {
+        code.push_str(&limit_check(
+            &format!("steps >= {max}"),
+            EvalError::OutOfFuel(max),
+        ));
+        code.push_str("steps += 1;");
+    }
+
     if opts.tracing_exec() {
         code.push_str("eprintln!(\"EXEC {:?}\", cur_frame.tr);");
     }
@@ -124,9 +381,9 @@ fn compile_toplevel(prog: &CPSProgram, opts: &CompilerOptions) -> String {
     for (name, eexprs) in prog.iter() {
         code.push_str(&format!("ThunkRef::{name} => {{"));
         code.push_str("/*");
-        code.push_str(&format!("{:?}", eexprs));
+        code.push_str(&escape_block_comment(&eexprs.iter().join(" ")));
         code.push_str("*/");
-        code.push_str(&compile_expr_cps_ref(eexprs, opts));
+        code.push_str(&compile_expr_cps_ref(eexprs, spans, opts));
         code.push_str("},");
     }
 
@@ -144,21 +401,116 @@ fn compile_toplevel(prog: &CPSProgram, opts: &CompilerOptions) -> String {
     code
 }
 
+/// Code that stops the program with the same error as the interpreter
+/// when `cond` holds.
+fn limit_check(cond: &str, e: EvalError) -> String {
+    format!("if {cond} {{ fail({}, {:?}); }}", e.code().0, e.to_string())
+}
+
+/// Rust block comments nest, so comment delimiters inside literals would
+/// unbalance the debug dump of each thunk.
+fn escape_block_comment(s: &str) -> String {
+    s.replace("/*", "/ *").replace("*/", "* /")
+}
+
 fn compile_instruction_tracing(code: &mut String, ee: &ExprCPSRef) {
     code.push_str(&match ee {
-        ExprCPSRef::IntegerLiteral(i) => format!("eprintln!(\"INST int {i}\");"),
-        ExprCPSRef::AtomLiteral(a) => format!("eprintln!(\"INST atom {a}\");"),
-        ExprCPSRef::ThunkRef(tf) => format!("eprintln!(\"INST tr {tf}\");"),
+        ExprCPSRef::IntegerLiteral(i, _) => format!("eprintln!(\"INST int {i}\");"),
+        ExprCPSRef::FloatLiteral(x, _) => {
+            format!("eprintln!(\"INST float {}\");", parser::float_literal(*x))
+        }
+        ExprCPSRef::AtomLiteral(a, _) => format!("eprintln!(\"INST atom {{}}\", {a:?});"),
+        ExprCPSRef::StringLiteral(s, _) => format!("eprintln!(\"INST string {{:?}}\", {s:?});"),
+        ExprCPSRef::CharLiteral(c, _) => format!("eprintln!(\"INST char {{:?}}\", {c:?});"),
+        ExprCPSRef::BytesLiteral(b, _) => format!("eprintln!(\"INST bytes {}\");", b.len()),
+        ExprCPSRef::Quotation(_, tf, _) => format!("eprintln!(\"INST quotation {tf}\");"),
+        ExprCPSRef::ListLiteral(es, _) => format!("eprintln!(\"INST list {}\");", es.len()),
+        ExprCPSRef::ListStart(_) => "eprintln!(\"INST list-start\");".to_string(),
+        ExprCPSRef::MapLiteral(es, _) => format!("eprintln!(\"INST map {}\");", es.len() / 2),
+        ExprCPSRef::ListEnd(_) => "eprintln!(\"INST list-end\");".to_string(),
+        ExprCPSRef::MapEnd(_) => "eprintln!(\"INST map-end\");".to_string(),
+        ExprCPSRef::ThunkRef(tf, _) => format!("eprintln!(\"INST tr {tf}\");"),
         ExprCPSRef::Terminate => "eprintln!(\"INST terminate\");".to_string(),
-        ExprCPSRef::Push => "eprintln!(\"INST push\");".to_string(),
-        ExprCPSRef::Pop => "eprintln!(\"INST pop\");".to_string(),
-        ExprCPSRef::ForceByCC => "eprintln!(\"INST force-cc\");".to_string(),
-        ExprCPSRef::ForceByCCBare => "eprintln!(\"INST force-cc-bare\");".to_string(),
+        ExprCPSRef::Push(_) => "eprintln!(\"INST push\");".to_string(),
+        ExprCPSRef::Pop(_) => "eprintln!(\"INST pop\");".to_string(),
+        ExprCPSRef::ForceByCC(_) => "eprintln!(\"INST force-cc\");".to_string(),
+        ExprCPSRef::ForceByCCBare(_) => "eprintln!(\"INST force-cc-bare\");".to_string(),
     })
 }
 
-fn compile_expr_cps_ref(eexprs: &[ExprCPSRef], opts: &CompilerOptions) -> String {
+/// Rust expression for a literal value, either pushed by the program or an
+/// element of a quotation. Quoted literals nest as deeply as the source
+/// does, so this doesn't recurse.
+fn literal_code(e: &ExprCPSRef) -> String {
+    enum Todo<'a> {
+        Literal(&'a ExprCPSRef),
+        Str(String),
+    }
+
     let mut code = String::new();
+    let mut todo = vec![Todo::Literal(e)];
+    while let Some(t) = todo.pop() {
+        let e = match t {
+            Todo::Literal(e) => e,
+            Todo::Str(s) => {
+                code.push_str(&s);
+                continue;
+            }
+        };
+        let (open, close) = match e {
+            ExprCPSRef::IntegerLiteral(i, _) => {
+                code.push_str(&format!("Value::Integer({i})"));
+                continue;
+            }
+            // Going through the bits keeps the exact value, including #inf and #nan
+            ExprCPSRef::FloatLiteral(x, _) => {
+                code.push_str(&format!("Value::Float(f64::from_bits({:#x}))", x.to_bits()));
+                continue;
+            }
+            ExprCPSRef::AtomLiteral(a, _) => {
+                code.push_str(&format!("Value::Atom({:?}.to_string())", a));
+                continue;
+            }
+            ExprCPSRef::StringLiteral(s, _) => {
+                code.push_str(&format!("Value::String({:?}.to_string())", s));
+                continue;
+            }
+            ExprCPSRef::CharLiteral(c, _) => {
+                code.push_str(&format!("Value::Char({:?})", c));
+                continue;
+            }
+            ExprCPSRef::BytesLiteral(b, _) => {
+                code.push_str(&format!("Value::Bytes(vec!{:?})", b));
+                continue;
+            }
+            ExprCPSRef::Quotation(_, tf, _) => (
+                "Value::Quotation { items: Rc::new(vec![",
+                format!("]), fp: ThunkRef::{tf} }}"),
+            ),
+            ExprCPSRef::ListLiteral(..) => ("Value::List(Rc::new(vec![", "]))".to_string()),
+            ExprCPSRef::MapLiteral(..) => ("map_from_items(vec![", "])".to_string()),
+            e => panic!("Not a literal: {e}"),
+        };
+        code.push_str(open);
+        todo.push(Todo::Str(close));
+        for (i, e) in e.items().unwrap_or_default().iter().enumerate().rev() {
+            todo.push(Todo::Literal(e));
+            if i > 0 {
+                todo.push(Todo::Str(",".to_string()));
+            }
+        }
+    }
+    code
+}
+
+fn compile_expr_cps_ref(
+    eexprs: &[ExprCPSRef],
+    spans: &mut SpanTable,
+    opts: &CompilerOptions,
+) -> String {
+    let mut code = String::new();
+    // The span the generated code last told the runtime about
+    let mut at = None;
 
     let mut exs = eexprs;
     loop {
@@ -174,30 +526,121 @@ fn compile_expr_cps_ref(eexprs: &[ExprCPSRef], opts: &CompilerOptions) -> String
             compile_instruction_tracing(&mut code, e);
         }
 
-        match e {
-            ExprCPSRef::IntegerLiteral(i) => {
-                code.push_str(&format!("stack.push(Value::Integer({i}));"))
+        // Anything that grows the stack can overflow it, except the names,
+        // functions and continuations the CPS conversion pushes for the
+        // next instructions to take straight back off
+        let taken = matches!(
+            exs,
+            [
+                ExprCPSRef::Push(_)
+                    | ExprCPSRef::Pop(_)
+                    | ExprCPSRef::ForceByCC(_)
+                    | ExprCPSRef::ForceByCCBare(_),
+                ..
+            ] | [ExprCPSRef::ThunkRef(..), ExprCPSRef::ForceByCC(_), ..]
+        );
+        let grows = match e {
+            ExprCPSRef::ForceByCC(_) => true,
+            ExprCPSRef::ListStart(_)
+            | ExprCPSRef::ListEnd(_)
+            | ExprCPSRef::MapEnd(_)
+            | ExprCPSRef::Pop(_)
+            | ExprCPSRef::ForceByCCBare(_)
+            | ExprCPSRef::Terminate => false,
+            _ => !taken,
+        };
+        let overflows = grows && opts.limits.max_stack.is_some();
+
+        // Only instructions that can fail need their place in the source
+        if let Some(s) = match e {
+            ExprCPSRef::MapLiteral(_, s)
+            | ExprCPSRef::ListEnd(s)
+            | ExprCPSRef::MapEnd(s)
+            | ExprCPSRef::Push(s)
+            | ExprCPSRef::Pop(s)
+            | ExprCPSRef::ForceByCC(s)
+            | ExprCPSRef::ForceByCCBare(s) => Some(s),
+            ExprCPSRef::IntegerLiteral(_, s)
+            | ExprCPSRef::FloatLiteral(_, s)
+            | ExprCPSRef::AtomLiteral(_, s)
+            | ExprCPSRef::StringLiteral(_, s)
+            | ExprCPSRef::CharLiteral(_, s)
+            | ExprCPSRef::BytesLiteral(_, s)
+            | ExprCPSRef::Quotation(_, _, s)
+            | ExprCPSRef::ListLiteral(_, s)
+            | ExprCPSRef::ThunkRef(_, s)
+                if overflows =>
+            {
+                Some(s)
+            }
+            _ => None,
+        } {
+            match spans.index(s) {
+                Some(i) if Some(i) != at => {
+                    code.push_str(&format!("AT.set({i});"));
+                    at = Some(i);
+                }
+                _ => {}
             }
-            ExprCPSRef::AtomLiteral(a) => {
-                code.push_str(&format!("stack.push(Value::Atom(\"{}\".to_string()));", a))
+        }
+
+        match e {
+            ExprCPSRef::IntegerLiteral(..)
+            | ExprCPSRef::FloatLiteral(..)
+            | ExprCPSRef::AtomLiteral(..)
+            | ExprCPSRef::StringLiteral(..)
+            | ExprCPSRef::CharLiteral(..)
+            | ExprCPSRef::BytesLiteral(..)
+            | ExprCPSRef::Quotation(..)
+            | ExprCPSRef::ListLiteral(..)
+            | ExprCPSRef::MapLiteral(_, _) => {
+                code.push_str(&format!("stack.push({});", literal_code(e)))
             }
 
-            ExprCPSRef::ThunkRef(tf) => code.push_str(&format!(
+            ExprCPSRef::ThunkRef(tf, _) => code.push_str(&format!(
                 "stack.push(Value::Thunk {{ env: cur_frame.env.clone(), fp: ThunkRef::{tf} }});"
             )),
 
             ExprCPSRef::Terminate => code.push_str("break;"),
 
-            ExprCPSRef::Push => code.push_str("builtin_push(&mut cur_frame.env, stack);"),
-            ExprCPSRef::Pop => code.push_str("builtin_pop(&mut cur_frame.env, stack);"),
+            ExprCPSRef::ListStart(_) => {
+                code.push_str("marks.push(stack.len());");
+                // The top level is a frame too
+                if let Some(max) = opts.limits.max_frames {
+                    code.push_str(&limit_check(
+                        &format!("marks.len() + 1 > {max}"),
+                        EvalError::TooManyFrames(max),
+                    ));
+                }
+            }
+            ExprCPSRef::ListEnd(_) => code.push_str("builtin_list_end(stack, &mut marks);"),
+            ExprCPSRef::MapEnd(_) => code.push_str("builtin_map_end(stack, &mut marks);"),
+
+            ExprCPSRef::Push(_) => code.push_str("builtin_push(&mut cur_frame.env, stack);"),
+            ExprCPSRef::Pop(_) => {
+                code.push_str("builtin_pop(&mut cur_frame.env, stack);");
+                if let Some(max) = opts.limits.max_env {
+                    code.push_str(&limit_check(
+                        &format!("cur_frame.env.bindings() > {max}"),
+                        EvalError::EnvTooLarge(max),
+                    ));
+                }
+            }
 
-            ExprCPSRef::ForceByCC => {
+            ExprCPSRef::ForceByCC(_) => {
                 code.push_str(r#"{ cur_frame = builtin_force_cc(stack, &mut cur_frame); }"#);
             }
-            ExprCPSRef::ForceByCCBare => {
+            ExprCPSRef::ForceByCCBare(_) => {
                 code.push_str(r#"{ cur_frame = builtin_force_cc_bare(stack); }"#)
             }
         }
+
+        if let (true, Some(max)) = (overflows, opts.limits.max_stack) {
+            code.push_str(&limit_check(
+                &format!("stack.len() > {max}"),
+                EvalError::StackOverflow(max),
+            ));
+        }
     }
 
     code
@@ -211,6 +654,9 @@ pub struct CompilerOptions {
     pub tracing_env: bool,
     pub tracing_instructions: bool,
     pub tracing_stack: bool,
+    /// Checked by the generated program, which stops with the
+    /// interpreter's error when one is exceeded
+    pub limits: EvalLimits,
 }
 
 impl CompilerOptions {
@@ -228,8 +674,26 @@ impl CompilerOptions {
     }
 }
 
-pub fn compile(exprs: &[Expr], opts: &CompilerOptions) -> String {
-    let expr_cps = cps::expr_cps(exprs);
+/// Compiles `exprs` to the source of a Rust program. Their spans must point
+/// into `sources`, so that runtime errors can say where they happened.
+/// Fails with the error the interpreter would give on what can be checked
+/// before running.
+pub fn compile(
+    exprs: &[Expr],
+    sources: &SourceMap,
+    opts: &CompilerOptions,
+) -> Result<String, EvalStacktrace> {
+    // One per file when several are compiled together
+    for e in exprs.iter() {
+        if let Expr::Pragma(p, span) = e {
+            p.check().map_err(|e| EvalStacktrace {
+                stack: vec![span.clone()],
+                error: e.into(),
+            })?;
+        }
+    }
+
+    let expr_cps = cps::expr_cps(exprs)?;
 
     if opts.debug {
         for e in expr_cps.iter() {
@@ -247,15 +711,19 @@ pub fn compile(exprs: &[Expr], opts: &CompilerOptions) -> String {
 
     let mut code = String::new();
 
+    code.push_str(&format!("mod bigint {{ {BIGINT} }} use bigint::BigInt;"));
+
     code.push_str(&filter_header(HEADER));
 
     code.push_str(&make_thunk_ref_enum(&prog3));
 
-    code.push_str(&compile_toplevel(&prog3, opts));
+    let mut spans = SpanTable::new(sources);
+    code.push_str(&compile_toplevel(&prog3, &mut spans, opts));
+    code.push_str(&spans.code());
 
     code.push_str(&main_function());
 
-    code
+    Ok(code)
 }
 
 fn filter_header(header: &str) -> String {
@@ -273,3 +741,141 @@ fn filter_header(header: &str) -> String {
     });
     lv.join("\n")
 }
+
+#[cfg(test)]
+mod test {
+    use chumsky::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_spans() {
+        let mut sources = SourceMap::new();
+        sources.add("lib.fpy", "");
+        let file = sources.add("main.fpy", "1\n  2 +");
+        let exprs = parser::file_parser(file)
+            .parse(sources.get(file).unwrap().src.as_str())
+            .unwrap();
+
+        let prog = expr_cps_to_program(&cps::expr_cps(&exprs).unwrap());
+        assert!(prog["entry"].contains(&ExprCPSRef::ForceByCC(Span::new(file, 6..7))));
+
+        let code = compile(&exprs, &sources, &Default::default()).unwrap();
+        assert!(code.contains(r#"pub static FILES: &[&str] = &["lib.fpy","main.fpy"];"#));
+        assert!(code.contains("pub static SPANS: &[(u32, u32, u32)] = &[(1,2,5)];"));
+        // Set once for both the push and the force of `+`
+        assert_eq!(code.matches("AT.set(0);").count(), 1);
+    }
+
+    #[test]
+    fn test_limits() {
+        let exprs = parser::parser().parse("[1] $x").unwrap();
+        let sources = SourceMap::new();
+
+        let code = compile(&exprs, &sources, &Default::default()).unwrap();
+        assert!(!code.contains("{ fail("));
+
+        let opts = CompilerOptions {
+            limits: EvalLimits {
+                fuel: Some(10),
+                max_stack: Some(20),
+                max_frames: Some(30),
+                max_env: Some(40),
+            },
+            ..Default::default()
+        };
+        let code = compile(&exprs, &sources, &opts).unwrap();
+        for check in [
+            r#"if steps >= 10 { fail(217, "Ran out of fuel after 10 steps"); }"#,
+            r#"if stack.len() > 20 { fail(218, "Stack grew past 20 values"); }"#,
+            r#"if marks.len() + 1 > 30 { fail(219, "More than 30 frames deep"); }"#,
+            r#"if cur_frame.env.bindings() > 40 { fail(220, "More than 40 bindings in one environment"); }"#,
+        ] {
+            assert!(code.contains(check), "{check}");
+        }
+
+        // After each literal and the call to `+`, not once per thunk
+        let exprs = parser::parser().parse("1 2 +").unwrap();
+        let code = compile(&exprs, &sources, &opts).unwrap();
+        assert_eq!(code.matches("stack.len() > 20").count(), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut sources = SourceMap::new();
+        let lib = sources.add("lib.fpy", "1 quote");
+        let main = sources.add("main.fpy", "#frospy 1\n2");
+        let mut exprs = vec![];
+        for file in [lib, main] {
+            exprs.extend(parser::parse_file(file, &sources.get(file).unwrap().src).0);
+        }
+
+        let e = compile(&exprs, &sources, &Default::default()).unwrap_err();
+        assert_eq!(e, crate::eval::eval(&exprs).unwrap_err());
+        assert_eq!(e.error.code(), crate::error_codes::TYPE_MISMATCH);
+
+        let exprs = parser::parser().parse("1 (2 quote)").unwrap();
+        let e = compile(&exprs, &sources, &Default::default()).unwrap_err();
+        assert_eq!(e.error.code(), crate::error_codes::BARE_QUOTE);
+        assert_eq!(e.stack, [Span::new(parser::FileId::default(), 5..10)]);
+    }
+
+    /// Nothing between parsing and code generation recurses on how long
+    /// or how deeply nested the program is.
+    #[test]
+    fn test_deep_input() {
+        let n = 50_000;
+        let flat = format!("0{}", " inc".repeat(n));
+        let n = 10_000;
+        let nested = format!("{}1{} force", "(".repeat(n), ") force".repeat(n - 1) + ")");
+        // Quoted and map literals
+        let n = 20_000;
+        let literal = |open: &str, close: &str| format!("{}1{}", open.repeat(n), close.repeat(n));
+        let quoted_thunk = format!("'{}", literal("(", ")"));
+        let quoted_list = format!("'{}", literal("[", "]"));
+        let quoted_map = format!("'{}", literal("{0 ", "}"));
+        let map = literal("{0 ", "}");
+
+        let mut sources = SourceMap::new();
+        for src in [flat, nested, quoted_thunk, quoted_list, quoted_map, map] {
+            let file = sources.add("deep.fpy", src);
+            let (exprs, diagnostics) = parser::parse_file(file, &sources.get(file).unwrap().src);
+            assert!(diagnostics.is_empty());
+
+            let stack = crate::eval::eval(&exprs).unwrap();
+            assert_eq!(stack.len(), 1);
+
+            let code = compile(&exprs, &sources, &Default::default()).unwrap();
+            assert!(code.contains("ThunkRef::entry =>"));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test_serde {
+    use chumsky::Parser;
+
+    use super::*;
+
+    fn round_trip<T>(t: &T) -> T
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        serde_json::from_str(&serde_json::to_string(t).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // JSON has no infinities or NaN, so they don't survive serde_json
+        let src = "#frospy 1
+($x ^x 1.5 +) $f '(a \"s\" #\\λ b\"\\x00\") [1 'b] {'k 2} 3 f";
+        let exprs = parser::parser().parse(src).unwrap();
+        assert_eq!(round_trip(&exprs), exprs);
+
+        let expr_cps = cps::expr_cps(&exprs[1..]).unwrap();
+        assert_eq!(round_trip(&expr_cps), expr_cps);
+
+        let prog = expr_cps_to_program(&expr_cps);
+        assert_eq!(round_trip(&prog), prog);
+    }
+}
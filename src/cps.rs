@@ -1,15 +1,35 @@
 use std::fmt::Display;
 
 use crate::{
+    eval::{EvalError, EvalStacktrace},
     parser::{self, Expr, Span},
     util,
 };
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExprCPS {
     IntegerLiteral(i64, Span),
+    FloatLiteral(f64, Span),
     AtomLiteral(String, Span),
+    StringLiteral(String, Span),
+    CharLiteral(char, Span),
+    BytesLiteral(Vec<u8>, Span),
+    /// The elements of a quoted thunk as literals, and the thunk itself.
+    /// A thunk quoted inside another has no thunk of its own, since the
+    /// outer one's code already has it, under the same span
+    Quotation(Vec<ExprCPS>, Option<Box<ExprCPS>>, Span),
+    /// A quoted list, whose elements are all literals
+    ListLiteral(Vec<ExprCPS>, Span),
+    /// A quoted map, as alternating keys and values
+    MapLiteral(Vec<ExprCPS>, Span),
     Thunk(Vec<ExprCPS>, Span),
+    /// Marks the stack height where a list literal's elements start
+    ListStart(Span),
+    /// Collects everything above the matching ListStart into a list
+    ListEnd(Span),
+    /// Like ListEnd, but pairs the elements up into a map
+    MapEnd(Span),
     Force(Span),
     ForceCC(Span),
     ForceCCBare(Span),
@@ -18,155 +38,457 @@ pub enum ExprCPS {
     Push(Span),
 }
 
-fn exprs_to_exprs_cps(exprs: &[Expr]) -> Vec<ExprCPS> {
-    let mut v2 = vec![];
-
-    let mut exs = exprs;
-    loop {
-        if exs.is_empty() {
-            break;
-        }
+/// A quoted expression as a literal. Quoting a thunk gives a quotation,
+/// which keeps its elements as data along with the code to run if the
+/// program turns it back into a thunk. Nested literals are converted on a
+/// stack of its own. Fails where the interpreter would.
+fn quoted_literal(e: &Expr, span: Span) -> Result<ExprCPS, EvalStacktrace> {
+    /// Each with the span its literal gets, and whether it is inside a
+    /// quoted thunk, whose code already has any thunk in it
+    enum Todo<'a> {
+        Quote(&'a Expr, Span, bool),
+        /// Takes its elements off the end of `done`
+        Assemble(&'a Expr, Span, bool),
+    }
 
-        let e;
-
-        (e, exs) = exs.split_first().unwrap();
-
-        match e {
-            Expr::Integer(i, s) => v2.push(ExprCPS::IntegerLiteral(*i, s.clone())),
-            Expr::Atom(a, atom_span) => match a.as_str() {
-                "quote" => {
-                    let qe;
-                    (qe, exs) = exs.split_first().unwrap();
-
-                    match qe {
-                        Expr::Integer(i, s) => v2.push(ExprCPS::IntegerLiteral(
-                            *i,
-                            parser::span_combine(atom_span, s),
-                        )),
-                        Expr::Atom(a, s) => v2.push(ExprCPS::AtomLiteral(
-                            a.to_string(),
-                            parser::span_combine(atom_span, s),
-                        )),
-                        Expr::Thunk(_, _) => panic!("Can't quote a thunk"),
-                    }
+    let mut todo = vec![Todo::Quote(e, span, false)];
+    let mut done = vec![];
+    while let Some(t) = todo.pop() {
+        let literal = match t {
+            Todo::Quote(e, span, in_thunk) => match e {
+                Expr::Integer(i, _) => ExprCPS::IntegerLiteral(*i, span),
+                Expr::Float(x, _) => ExprCPS::FloatLiteral(*x, span),
+                Expr::Atom(a, _) => ExprCPS::AtomLiteral(a.to_string(), span),
+                Expr::String(st, _) => ExprCPS::StringLiteral(st.to_string(), span),
+                Expr::Char(c, _) => ExprCPS::CharLiteral(*c, span),
+                Expr::Bytes(b, _) => ExprCPS::BytesLiteral(b.clone(), span),
+                Expr::Thunk(es, _) | Expr::List(es, _) | Expr::Map(es, _) => {
+                    let inner = in_thunk || matches!(e, Expr::Thunk(..));
+                    todo.push(Todo::Assemble(e, span, in_thunk));
+                    todo.extend(
+                        es.iter()
+                            .rev()
+                            .map(|e| Todo::Quote(e, e.get_span().clone(), inner)),
+                    );
+                    continue;
                 }
-                "push" => v2.push(ExprCPS::Push(atom_span.clone())),
-                "pop" => v2.push(ExprCPS::Pop(atom_span.clone())),
-                "force" => v2.push(ExprCPS::Force(atom_span.clone())),
-                a => {
-                    v2.push(ExprCPS::AtomLiteral(a.to_string(), atom_span.clone()));
-                    v2.push(ExprCPS::Push(atom_span.clone()));
-                    v2.push(ExprCPS::Force(atom_span.clone()));
+                // Only the first file can start with one, but a file before
+                // it can end with `quote`
+                Expr::Pragma(_, ps) => {
+                    return Err(EvalStacktrace {
+                        stack: vec![ps.clone()],
+                        error: EvalError::TypeMismatch(
+                            "quotable expression".to_string(),
+                            "pragma".to_string(),
+                        ),
+                    })
                 }
             },
-            Expr::Thunk(vec, s) => {
-                v2.push(ExprCPS::Thunk(exprs_to_exprs_cps(vec), s.clone()));
+            Todo::Assemble(e, span, in_thunk) => {
+                let n = e.children().map_or(0, <[Expr]>::len);
+                let items = done.split_off(done.len() - n);
+                match e {
+                    Expr::Thunk(es, s) if !in_thunk => {
+                        let thunk = ExprCPS::Thunk(exprs_to_exprs_cps(es)?, s.clone());
+                        ExprCPS::Quotation(items, Some(Box::new(thunk)), span)
+                    }
+                    Expr::Thunk(..) => ExprCPS::Quotation(items, None, span),
+                    Expr::List(..) => ExprCPS::ListLiteral(items, span),
+                    _ => ExprCPS::MapLiteral(items, span),
+                }
+            }
+        };
+        done.push(literal);
+    }
+    Ok(done.pop().unwrap())
+}
+
+/// The block an `exprs_to_exprs_cps` frame is the body of.
+enum Block {
+    Top,
+    Thunk(Span),
+    List(Span),
+    Map(Span),
+}
+
+/// Keeps its own stack of the blocks it is inside, rather than recursing,
+/// so deeply nested input can't overflow the native one.
+fn exprs_to_exprs_cps(exprs: &[Expr]) -> Result<Vec<ExprCPS>, EvalStacktrace> {
+    struct Frame<'a> {
+        rest: &'a [Expr],
+        out: Vec<ExprCPS>,
+        block: Block,
+    }
+
+    let mut frames = vec![Frame {
+        rest: exprs,
+        out: vec![],
+        block: Block::Top,
+    }];
+
+    loop {
+        let top = frames.last_mut().unwrap();
+
+        let Some((e, rest)) = top.rest.split_first() else {
+            let done = frames.pop().unwrap();
+            let Some(parent) = frames.last_mut() else {
+                return Ok(done.out);
+            };
+            let v2 = &mut parent.out;
+            match done.block {
+                Block::Top => unreachable!("Only the first frame is the top"),
+                Block::Thunk(s) => v2.push(ExprCPS::Thunk(done.out, s)),
+                // The elements run in a thunk of their own, so they can
+                // force things and get their own scope
+                Block::List(s) => {
+                    v2.push(ExprCPS::Thunk(done.out, s.clone()));
+                    v2.push(ExprCPS::Force(s.clone()));
+                    v2.push(ExprCPS::ListEnd(s));
+                }
+                Block::Map(s) => {
+                    v2.push(ExprCPS::Thunk(done.out, s.clone()));
+                    v2.push(ExprCPS::Force(s.clone()));
+                    v2.push(ExprCPS::MapEnd(s));
+                }
+            }
+            continue;
+        };
+        top.rest = rest;
+        let v2 = &mut top.out;
+
+        let (body, block) = match e {
+            Expr::Integer(i, s) => {
+                v2.push(ExprCPS::IntegerLiteral(*i, s.clone()));
+                continue;
             }
+            Expr::Float(x, s) => {
+                v2.push(ExprCPS::FloatLiteral(*x, s.clone()));
+                continue;
+            }
+            Expr::String(st, s) => {
+                v2.push(ExprCPS::StringLiteral(st.to_string(), s.clone()));
+                continue;
+            }
+            Expr::Char(c, s) => {
+                v2.push(ExprCPS::CharLiteral(*c, s.clone()));
+                continue;
+            }
+            Expr::Bytes(b, s) => {
+                v2.push(ExprCPS::BytesLiteral(b.clone(), s.clone()));
+                continue;
+            }
+            Expr::Atom(a, atom_span) => {
+                match a.as_str() {
+                    "quote" => {
+                        let Some((qe, rest)) = top.rest.split_first() else {
+                            return Err(EvalStacktrace {
+                                stack: vec![atom_span.clone()],
+                                error: EvalError::BareQuote,
+                            });
+                        };
+                        top.rest = rest;
+
+                        v2.push(quoted_literal(
+                            qe,
+                            parser::span_combine(atom_span, qe.get_span()),
+                        )?);
+                    }
+                    "push" => v2.push(ExprCPS::Push(atom_span.clone())),
+                    "pop" => v2.push(ExprCPS::Pop(atom_span.clone())),
+                    "force" => v2.push(ExprCPS::Force(atom_span.clone())),
+                    a => {
+                        v2.push(ExprCPS::AtomLiteral(a.to_string(), atom_span.clone()));
+                        v2.push(ExprCPS::Push(atom_span.clone()));
+                        v2.push(ExprCPS::Force(atom_span.clone()));
+                    }
+                }
+                continue;
+            }
+            Expr::Thunk(vec, s) => (vec, Block::Thunk(s.clone())),
+            Expr::List(vec, s) => {
+                v2.push(ExprCPS::ListStart(s.clone()));
+                (vec, Block::List(s.clone()))
+            }
+            Expr::Map(vec, s) => {
+                v2.push(ExprCPS::ListStart(s.clone()));
+                (vec, Block::Map(s.clone()))
+            }
+            // Checked by compiler2::compile, nothing to run
+            Expr::Pragma(..) => continue,
+        };
+
+        frames.push(Frame {
+            rest: body,
+            out: vec![],
+            block,
+        });
+    }
+}
+
+impl ExprCPS {
+    /// Moves out the expressions nested in this one.
+    fn take_children(&mut self) -> Vec<ExprCPS> {
+        match self {
+            ExprCPS::Quotation(es, thunk, _) => {
+                let mut v = std::mem::take(es);
+                v.extend(thunk.take().map(|t| *t));
+                v
+            }
+            ExprCPS::ListLiteral(es, _) | ExprCPS::MapLiteral(es, _) | ExprCPS::Thunk(es, _) => {
+                std::mem::take(es)
+            }
+            _ => vec![],
         }
     }
+}
 
-    v2
+/// Everything after a force ends up in a thunk nested in the one before
+/// it, so a long flat program nests as deeply as it is long. Dropping it
+/// mustn't recurse.
+impl Drop for ExprCPS {
+    fn drop(&mut self) {
+        let mut todo = self.take_children();
+        while let Some(mut e) = todo.pop() {
+            todo.append(&mut e.take_children());
+        }
+    }
 }
 
 impl Display for ExprCPS {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ExprCPS::IntegerLiteral(i, _) => f.write_fmt(format_args!("{}", i)),
-            ExprCPS::AtomLiteral(a, _) => f.write_fmt(format_args!("'{}", a)),
-            ExprCPS::Thunk(es, _) => {
-                f.write_str("( ")?;
-                for e in es.iter() {
-                    f.write_fmt(format_args!("{} ", e))?;
+        enum Todo<'a> {
+            Expr(&'a ExprCPS),
+            Str(&'static str),
+        }
+
+        let mut todo = vec![Todo::Expr(self)];
+        while let Some(t) = todo.pop() {
+            let e = match t {
+                Todo::Expr(e) => e,
+                Todo::Str(s) => {
+                    f.write_str(s)?;
+                    continue;
+                }
+            };
+            let (open, es, close): (_, &[ExprCPS], _) = match e {
+                ExprCPS::IntegerLiteral(i, _) => {
+                    f.write_fmt(format_args!("{}", i))?;
+                    continue;
+                }
+                ExprCPS::FloatLiteral(x, _) => {
+                    f.write_str(&parser::float_literal(*x))?;
+                    continue;
+                }
+                ExprCPS::AtomLiteral(a, _) => {
+                    f.write_fmt(format_args!("'{}", a))?;
+                    continue;
+                }
+                ExprCPS::StringLiteral(s, _) => {
+                    f.write_fmt(format_args!("{:?}", s))?;
+                    continue;
+                }
+                ExprCPS::CharLiteral(c, _) => {
+                    f.write_str(&parser::char_literal(*c))?;
+                    continue;
+                }
+                ExprCPS::BytesLiteral(b, _) => {
+                    f.write_str(&parser::bytes_literal(b))?;
+                    continue;
                 }
-                f.write_str(")")
+                ExprCPS::Quotation(es, _, _) => ("'( ", es, ")"),
+                ExprCPS::ListLiteral(es, _) => ("'[ ", es, "]"),
+                ExprCPS::MapLiteral(es, _) => ("'{ ", es, "}"),
+                ExprCPS::Thunk(es, _) => ("( ", es, ")"),
+                ExprCPS::ListStart(_) => ("list-start", &[], ""),
+                ExprCPS::ListEnd(_) => ("list-end", &[], ""),
+                ExprCPS::MapEnd(_) => ("map-end", &[], ""),
+                ExprCPS::Force(_) => ("force", &[], ""),
+                ExprCPS::ForceCC(_) => ("forceCC", &[], ""),
+                ExprCPS::ForceCCBare(_) => ("forceCCbare", &[], ""),
+                ExprCPS::Terminate => ("terminate", &[], ""),
+                ExprCPS::Pop(_) => ("pop", &[], ""),
+                ExprCPS::Push(_) => ("push", &[], ""),
+            };
+            f.write_str(open)?;
+            todo.push(Todo::Str(close));
+            for e in es.iter().rev() {
+                todo.push(Todo::Str(" "));
+                todo.push(Todo::Expr(e));
             }
-            ExprCPS::Force(_) => f.write_fmt(format_args!("force")),
-            ExprCPS::ForceCC(_) => f.write_fmt(format_args!("forceCC")),
-            ExprCPS::ForceCCBare(_) => f.write_fmt(format_args!("forceCCbare")),
-            ExprCPS::Terminate => f.write_fmt(format_args!("terminate")),
-            ExprCPS::Pop(_) => f.write_fmt(format_args!("pop")),
-            ExprCPS::Push(_) => f.write_fmt(format_args!("push")),
         }
+        Ok(())
     }
 }
 
-fn cps_thunk(exprs: &[ExprCPS], span: &Span) -> ExprCPS {
+/// What a converted thunk starts with, binding the continuation it is
+/// passed, and the continuation its body ends with.
+fn thunk_cc() -> (Vec<ExprCPS>, Vec<ExprCPS>) {
     let cc = util::random_name();
     let cc_atom = ExprCPS::AtomLiteral(cc, parser::dummy_span());
     let cont = vec![cc_atom.clone(), ExprCPS::Push(parser::dummy_span())];
-
-    let mut v = vec![cc_atom.clone(), ExprCPS::Pop(parser::dummy_span())];
-
-    v.extend(cps_internal(exprs, &cont));
-
-    ExprCPS::Thunk(v, span.clone())
+    let prefix = vec![cc_atom, ExprCPS::Pop(parser::dummy_span())];
+    (prefix, cont)
 }
 
-fn cps_internal(exprs: &[ExprCPS], cont: &[ExprCPS]) -> Vec<ExprCPS> {
-    let mut ne = vec![];
+/// Converts `exprs` into `out`, which ends by passing control to `cont`.
+/// Nested thunks, whatever follows a force and quoted literals are
+/// converted by frames on a stack of its own, rather than by recursing.
+fn cps_internal(exprs: &[ExprCPS], out: Vec<ExprCPS>, cont: &[ExprCPS]) -> Vec<ExprCPS> {
+    /// Where a frame's output goes once it is done.
+    enum Done<'a> {
+        Return,
+        /// A thunk in the parent's body
+        Thunk(Span),
+        /// What runs after the parent's last force, which is then done
+        Continuation(Span),
+        /// The elements of a quoted list, map or quotation
+        Literal(&'a ExprCPS),
+        /// The code of a quotation, with its elements
+        Quotation(Vec<ExprCPS>, &'a ExprCPS),
+    }
 
-    let mut exs = exprs;
+    struct Frame<'a> {
+        rest: &'a [ExprCPS],
+        cont: Vec<ExprCPS>,
+        out: Vec<ExprCPS>,
+        is_bare: bool,
+        done: Done<'a>,
+    }
 
-    let mut is_bare = true;
+    let mut frames = vec![Frame {
+        rest: exprs,
+        cont: cont.to_vec(),
+        out,
+        is_bare: true,
+        done: Done::Return,
+    }];
 
     loop {
-        if exs.is_empty() {
-            break;
-        }
-
-        let e;
-
-        (e, exs) = exs.split_first().unwrap();
+        let top = frames.last_mut().unwrap();
 
-        let mut forcing = false;
-
-        ne.extend(match e {
-            ExprCPS::Thunk(te, s) => vec![cps_thunk(te, s)],
+        let Some((e, exs)) = top.rest.split_first() else {
+            let mut done = frames.pop().unwrap();
+            if done.is_bare {
+                done.out.extend(done.cont.iter().cloned());
+                done.out.push(ExprCPS::ForceCCBare(parser::dummy_span()));
+            }
+            let out = done.out;
+            match (done.done, frames.last_mut()) {
+                (Done::Return, _) => return out,
+                (Done::Thunk(s), Some(parent)) => {
+                    parent.out.push(ExprCPS::Thunk(out, s));
+                }
+                (Done::Continuation(s), Some(parent)) => {
+                    parent.out.push(ExprCPS::Thunk(out, s.clone()));
+                    parent.out.push(ExprCPS::ForceCC(s));
+                }
+                (Done::Literal(e), Some(parent)) => match e {
+                    ExprCPS::ListLiteral(_, s) => {
+                        parent.out.push(ExprCPS::ListLiteral(out, s.clone()));
+                    }
+                    ExprCPS::MapLiteral(_, s) => {
+                        parent.out.push(ExprCPS::MapLiteral(out, s.clone()));
+                    }
+                    ExprCPS::Quotation(_, None, s) => {
+                        parent.out.push(ExprCPS::Quotation(out, None, s.clone()));
+                    }
+                    ExprCPS::Quotation(_, Some(thunk), _) => {
+                        let ExprCPS::Thunk(te, _) = thunk.as_ref() else {
+                            unreachable!("A quotation's code is a thunk")
+                        };
+                        let (prefix, cont) = thunk_cc();
+                        frames.push(Frame {
+                            rest: te,
+                            cont,
+                            out: prefix,
+                            is_bare: true,
+                            done: Done::Quotation(out, e),
+                        });
+                    }
+                    _ => unreachable!("Only literals have elements"),
+                },
+                (Done::Quotation(items, e), Some(parent)) => {
+                    let ExprCPS::Quotation(_, Some(thunk), s) = e else {
+                        unreachable!("Only quotations with code get here")
+                    };
+                    let ts = match thunk.as_ref() {
+                        ExprCPS::Thunk(_, ts) => ts.clone(),
+                        _ => unreachable!("A quotation's code is a thunk"),
+                    };
+                    let thunk = ExprCPS::Thunk(out, ts);
+                    parent
+                        .out
+                        .push(ExprCPS::Quotation(items, Some(Box::new(thunk)), s.clone()));
+                }
+                (_, None) => unreachable!("Only the first frame returns"),
+            }
+            continue;
+        };
+        top.rest = exs;
+
+        let frame = match e {
+            ExprCPS::Thunk(te, s) => {
+                let (prefix, cont) = thunk_cc();
+                Frame {
+                    rest: te,
+                    cont,
+                    out: prefix,
+                    is_bare: true,
+                    done: Done::Thunk(s.clone()),
+                }
+            }
+            ExprCPS::Quotation(items, _, _)
+            | ExprCPS::ListLiteral(items, _)
+            | ExprCPS::MapLiteral(items, _) => Frame {
+                rest: items,
+                cont: vec![],
+                out: vec![],
+                is_bare: false,
+                done: Done::Literal(e),
+            },
             ExprCPS::Force(s) => {
-                let mut v = vec![];
+                top.is_bare = false;
+                top.rest = &[];
                 if exs.is_empty() {
                     // Note that this is very important. Otherwise, you
                     // a new thunk (cont forcebare), capturing the
                     // current environment. When in a Y-combinator
                     // "loop", this repeatedly adds a new thunk around
                     // the continuation, leading to a memory leak.
-                    v.extend(cont.iter().cloned());
-                } else {
-                    v.push(ExprCPS::Thunk(cps_internal(exs, cont), s.clone()));
+                    top.out.extend(top.cont.iter().cloned());
+                    top.out.push(ExprCPS::ForceCC(s.clone()));
+                    continue;
+                }
+                Frame {
+                    rest: exs,
+                    cont: top.cont.clone(),
+                    out: vec![],
+                    is_bare: true,
+                    done: Done::Continuation(s.clone()),
                 }
-                v.push(ExprCPS::ForceCC(s.clone()));
-                is_bare = false;
-                forcing = true;
-                v
             }
             ExprCPS::ForceCC(_) => todo!(),
             ExprCPS::ForceCCBare(_) => todo!(),
-            x => vec![x.clone()],
-        });
-
-        if forcing {
-            break;
-        }
-    }
-
-    if is_bare {
-        ne.extend(cont.iter().cloned());
-        ne.push(ExprCPS::ForceCCBare(parser::dummy_span()));
+            x => {
+                top.out.push(x.clone());
+                continue;
+            }
+        };
+        frames.push(frame);
     }
-
-    ne
 }
 
-pub fn expr_cps(exprs: &[Expr]) -> Vec<ExprCPS> {
-    let exprs = exprs_to_exprs_cps(exprs);
+pub fn expr_cps(exprs: &[Expr]) -> Result<Vec<ExprCPS>, EvalStacktrace> {
+    let exprs = exprs_to_exprs_cps(exprs)?;
 
-    cps_internal(
+    Ok(cps_internal(
         &exprs,
+        vec![],
         &[ExprCPS::Thunk(
             vec![ExprCPS::Terminate],
             parser::dummy_span(),
         )],
-    )
+    ))
 }
 
 // Notes from prior attempts:
@@ -0,0 +1,423 @@
+//! A lossless syntax tree. Every character of the source belongs either to
+//! a node or to the trivia before one, spans are exact, and the sugar that
+//! `Expr` expands (`'`, `$` and `^`) is kept as written. `Expr` is lowered
+//! from this, so formatters and linters can work here instead.
+
+use chumsky::prelude::*;
+use chumsky::Parser;
+
+use crate::parser::{
+    bytes_literal_parser, comment_parser, expand_atom, is_delimiter, parse_char, parse_float,
+    parse_integer, string_literal, word, AtomMod, Comment, CommentKind, Expr, FileId, Pragma, Span,
+};
+
+/// Whatever separates tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    Whitespace(String, Span),
+    Comment(Comment),
+}
+
+impl Trivia {
+    pub fn span(&self) -> &Span {
+        match self {
+            Trivia::Whitespace(_, s) => s,
+            Trivia::Comment(c) => &c.span,
+        }
+    }
+
+    /// Exactly as it appeared in the source.
+    pub fn text(&self) -> String {
+        match self {
+            Trivia::Whitespace(s, _) => s.clone(),
+            Trivia::Comment(Comment {
+                kind: CommentKind::Line,
+                text,
+                ..
+            }) => format!(";{text}"),
+            Trivia::Comment(Comment {
+                kind: CommentKind::Block,
+                text,
+                ..
+            }) => format!("#|{text}|#"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Delimiter {
+    /// `( ... )`, a thunk
+    Paren,
+    /// `[ ... ]`, a list
+    Bracket,
+    /// `{ ... }`, a map
+    Brace,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    /// `#!...`, only ever the first node
+    Shebang(String),
+    Pragma(Pragma),
+    Integer(i64),
+    Float(f64),
+    Char(char),
+    String(String),
+    Bytes(Vec<u8>),
+    Atom(Option<AtomMod>, String),
+    /// `'` and then a thunk, list or map
+    Quote(Box<Node>),
+    Group {
+        delimiter: Delimiter,
+        open: Span,
+        children: Vec<Node>,
+        /// Between the last child and the closing delimiter
+        trailing: Vec<Trivia>,
+        close: Span,
+    },
+    /// A group that didn't parse, skipped so the rest of the file can be
+    Error(Delimiter),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub leading: Vec<Trivia>,
+    pub kind: NodeKind,
+    /// Just the node, without the leading trivia
+    pub span: Span,
+}
+
+impl Node {
+    /// The `Expr`s this stands for. Sigils expand to several atoms sharing
+    /// the node's span.
+    pub fn lower(&self) -> Vec<Expr> {
+        let span = self.span.clone();
+        match &self.kind {
+            NodeKind::Shebang(_) | NodeKind::Error(_) => vec![],
+            NodeKind::Pragma(p) => vec![Expr::Pragma(p.clone(), span)],
+            NodeKind::Integer(i) => vec![Expr::Integer(*i, span)],
+            NodeKind::Float(f) => vec![Expr::Float(*f, span)],
+            NodeKind::Char(c) => vec![Expr::Char(*c, span)],
+            NodeKind::String(s) => vec![Expr::String(s.clone(), span)],
+            NodeKind::Bytes(b) => vec![Expr::Bytes(b.clone(), span)],
+            NodeKind::Atom(m, s) => expand_atom(*m, s, &span),
+            NodeKind::Quote(n) => {
+                let quote = Span::new(span.file, span.start()..span.start() + 1);
+                std::iter::once(Expr::Atom("quote".to_string(), quote))
+                    .chain(n.lower())
+                    .collect()
+            }
+            NodeKind::Group {
+                delimiter,
+                children,
+                ..
+            } => {
+                let es = children.iter().flat_map(Node::lower).collect();
+                vec![match delimiter {
+                    Delimiter::Paren => Expr::Thunk(es, span),
+                    Delimiter::Bracket => Expr::List(es, span),
+                    Delimiter::Brace => Expr::Map(es, span),
+                }]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cst {
+    pub nodes: Vec<Node>,
+    /// After the last node
+    pub trailing: Vec<Trivia>,
+}
+
+impl Cst {
+    pub fn lower(&self) -> Vec<Expr> {
+        self.nodes.iter().flat_map(Node::lower).collect()
+    }
+}
+
+pub(crate) fn trivia(file: FileId) -> impl Parser<char, Vec<Trivia>, Error = Simple<char>> + Clone {
+    choice((
+        filter(|c: &char| c.is_whitespace())
+            .repeated()
+            .at_least(1)
+            .collect::<String>()
+            .map_with_span(move |s, span| Trivia::Whitespace(s, Span::new(file, span))),
+        comment_parser().map(move |c| {
+            Trivia::Comment(Comment {
+                span: Span::new(file, c.span.range),
+                ..c
+            })
+        }),
+    ))
+    .repeated()
+}
+
+/// A node with the trivia before it.
+pub(crate) fn node(
+    file: FileId,
+    kind: impl Parser<char, NodeKind, Error = Simple<char>> + Clone,
+) -> impl Parser<char, Node, Error = Simple<char>> + Clone {
+    trivia(file)
+        .then(kind.map_with_span(move |kind, span| (kind, Span::new(file, span))))
+        .map(|(leading, (kind, span))| Node {
+            leading,
+            kind,
+            span,
+        })
+}
+
+pub(crate) fn atom() -> impl Parser<char, NodeKind, Error = Simple<char>> + Clone {
+    choice((
+        just('\'').to(AtomMod::Quote),
+        just('$').to(AtomMod::QuotePop),
+        just('^').to(AtomMod::QuotePush),
+    ))
+    .or_not()
+    .then(word().try_map(|w, span| {
+        let message = match (parse_integer(&w), parse_float(&w)) {
+            (None, None) => return Ok(w),
+            // Keep the range error if this is what chumsky reports
+            (Some(Err(e)), _) | (_, Some(Err(e))) => e,
+            _ => "expected atom, found number".to_string(),
+        };
+        Err(Simple::custom(span, message))
+    }))
+    .labelled("atom")
+    .map(|(m, s)| NodeKind::Atom(m, s))
+}
+
+pub(crate) fn string() -> impl Parser<char, NodeKind, Error = Simple<char>> + Clone {
+    string_literal().labelled("string").map(NodeKind::String)
+}
+
+pub fn parser() -> impl Parser<char, Cst, Error = Simple<char>> {
+    file_parser(FileId::default())
+}
+
+pub fn file_parser(file: FileId) -> impl Parser<char, Cst, Error = Simple<char>> {
+    let character = just("#\\")
+        .ignore_then(
+            any()
+                .chain(filter(|c: &char| !is_delimiter(*c)).repeated())
+                .collect::<String>(),
+        )
+        .try_map(|s, span| parse_char(&s).map_err(|e| Simple::custom(span, e)))
+        .labelled("char")
+        .map(NodeKind::Char);
+
+    let bytes = bytes_literal_parser()
+        .labelled("byte string")
+        .map(NodeKind::Bytes);
+
+    let integer = word()
+        .try_map(|w, span| match parse_integer(&w) {
+            Some(r) => r.map_err(|e| Simple::custom(span, e)),
+            None => Err(Simple::custom(span, "expected integer")),
+        })
+        .labelled("integer")
+        .map(NodeKind::Integer);
+
+    let float = word()
+        .try_map(|w, span| match parse_float(&w) {
+            Some(r) => r.map_err(|e| Simple::custom(span, e)),
+            None => Err(Simple::custom(span, "expected float")),
+        })
+        .or(just('#')
+            .ignore_then(word())
+            .try_map(|w, span| match w.as_str() {
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                "nan" => Ok(f64::NAN),
+                _ => Err(Simple::custom(span, "expected #inf, #-inf or #nan")),
+            }))
+        .labelled("float")
+        .map(NodeKind::Float);
+
+    let mut expr = Recursive::declare();
+
+    let group = |open: char, close: char, delimiter: Delimiter| {
+        let others = [('(', ')'), ('[', ']'), ('{', '}')]
+            .into_iter()
+            .filter(|(o, _)| *o != open)
+            .collect::<Vec<_>>();
+        just(open)
+            .map_with_span(move |_, span| Span::new(file, span))
+            .then(expr.clone().repeated())
+            .then(trivia(file))
+            .then(just(close).map_with_span(move |_, span| Span::new(file, span)))
+            .map(
+                move |(((open, children), trailing), close)| NodeKind::Group {
+                    delimiter,
+                    open,
+                    children,
+                    trailing,
+                    close,
+                },
+            )
+            .recover_with(nested_delimiters(
+                open,
+                close,
+                [others[0], others[1]],
+                move |_| NodeKind::Error(delimiter),
+            ))
+    };
+
+    let thunk = group('(', ')', Delimiter::Paren).labelled("thunk");
+    let list = group('[', ']', Delimiter::Bracket).labelled("list");
+    // Alternating keys and values
+    let map = group('{', '}', Delimiter::Brace).labelled("map");
+
+    // Like the atom sigils, '( ... ) is sugar for quote ( ... ), and
+    // the same goes for lists and maps
+    let quotation = just('\'')
+        .ignore_then(node(
+            file,
+            choice((thunk.clone(), list.clone(), map.clone())),
+        ))
+        .map(|n| NodeKind::Quote(Box::new(n)))
+        .labelled("quotation");
+
+    expr.define(node(
+        file,
+        choice((
+            integer,
+            character,
+            float,
+            bytes,
+            atom(),
+            string(),
+            quotation,
+            thunk,
+            list,
+            map,
+        ))
+        .labelled("expr"),
+    ));
+
+    // Only the very first line, so `chmod +x` scripts work
+    let shebang = just("#!")
+        .ignore_then(filter(|c: &char| *c != '\n').repeated())
+        .collect::<String>()
+        .map_with_span(move |s, span| Node {
+            leading: vec![],
+            kind: NodeKind::Shebang(format!("#!{s}")),
+            span: Span::new(file, span),
+        });
+
+    // Ends at a comment so one can follow it on the same line
+    let pragma = just("#frospy")
+        .ignore_then(filter(|c: &char| *c != '\n' && *c != ';').repeated())
+        .collect::<String>()
+        .try_map(|rest, span| {
+            let mut words = rest.split_whitespace();
+            let version = words
+                .next()
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| Simple::custom(span, "expected language version"))?;
+            Ok(Pragma {
+                version,
+                extensions: words.map(|w| w.to_string()).collect(),
+            })
+        })
+        .labelled("pragma")
+        .map(NodeKind::Pragma);
+
+    shebang
+        .or_not()
+        .then(node(file, pragma).or_not())
+        .then(expr.repeated())
+        .then(trivia(file))
+        .then_ignore(end())
+        .map(|(((shebang, pragma), nodes), trailing)| Cst {
+            nodes: shebang.into_iter().chain(pragma).chain(nodes).collect(),
+            trailing,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sp(range: std::ops::Range<usize>) -> Span {
+        Span::new(FileId::default(), range)
+    }
+
+    /// The spans of every node and piece of trivia, in source order.
+    fn tiles(nodes: &[Node], trailing: &[Trivia], out: &mut Vec<Span>) {
+        for n in nodes.iter() {
+            out.extend(n.leading.iter().map(|t| t.span().clone()));
+            match &n.kind {
+                NodeKind::Group {
+                    open,
+                    children,
+                    trailing,
+                    close,
+                    ..
+                } => {
+                    out.push(open.clone());
+                    tiles(children, trailing, out);
+                    out.push(close.clone());
+                }
+                NodeKind::Quote(inner) => {
+                    out.push(sp(n.span.start()..n.span.start() + 1));
+                    tiles(std::slice::from_ref(inner), &[], out);
+                }
+                _ => out.push(n.span.clone()),
+            }
+        }
+        out.extend(trailing.iter().map(|t| t.span().clone()));
+    }
+
+    #[test]
+    fn test_lossless() {
+        let src = "#!/usr/bin/env frospy\n#frospy 1\n ( $x #| c |# 'y) ; end\n' [ 1 ]\t{ } ";
+        let cst = parser().parse(src).unwrap();
+
+        let mut spans = vec![];
+        tiles(&cst.nodes, &cst.trailing, &mut spans);
+        let mut pos = 0;
+        for s in spans.iter() {
+            assert_eq!(s.start(), pos, "gap before {s:?}");
+            pos = s.end();
+        }
+        assert_eq!(pos, src.chars().count());
+
+        let thunk = &cst.nodes[2];
+        assert_eq!(thunk.span, sp(33..49));
+        assert_eq!(
+            thunk.leading.iter().map(Trivia::text).collect::<String>(),
+            "\n "
+        );
+        match &thunk.kind {
+            NodeKind::Group {
+                children, trailing, ..
+            } => {
+                assert_eq!(
+                    children[0].kind,
+                    NodeKind::Atom(Some(AtomMod::QuotePop), "x".to_string())
+                );
+                assert_eq!(children[0].span, sp(35..37));
+                assert_eq!(children[1].leading[1].text(), "#| c |#");
+                assert!(trailing.is_empty());
+            }
+            k => panic!("expected thunk, got {k:?}"),
+        }
+        assert_eq!(cst.trailing.last().unwrap().text(), " ");
+    }
+
+    #[test]
+    fn test_lower() {
+        let cst = parser().parse("'( x ) $y").unwrap();
+        assert_eq!(
+            cst.lower(),
+            vec![
+                Expr::Atom("quote".to_string(), sp(0..1)),
+                Expr::Thunk(vec![Expr::Atom("x".to_string(), sp(3..4))], sp(1..6)),
+                Expr::Atom("quote".to_string(), sp(7..9)),
+                Expr::Atom("y".to_string(), sp(7..9)),
+                Expr::Atom("pop".to_string(), sp(7..9)),
+            ]
+        );
+    }
+}
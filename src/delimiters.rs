@@ -0,0 +1,381 @@
+//! Balances `()`, `[]` and `{}` before parsing. The parser can only say it
+//! ran out of input when a `(` is never closed, so this finds the `(` that
+//! is most likely missing its `)`, reports it, and repairs the source so
+//! the parser can carry on and report whatever else is wrong.
+
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::{
+    diagnostic::Diagnostic,
+    error_codes,
+    parser::{FileId, Span},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Code,
+    String,
+    Block(usize),
+    Line,
+}
+
+struct Open {
+    delimiter: char,
+    pos: usize,
+    /// Index into the dedents of the scan
+    id: usize,
+}
+
+/// What one reading of the source found, before it's repaired.
+struct Scan {
+    diagnostics: Vec<Diagnostic>,
+    /// Where each missing closer goes, and its text
+    insertions: Vec<(usize, String)>,
+    /// Closers with nothing to close
+    replaced: Vec<usize>,
+}
+
+fn closer(open: char) -> char {
+    match open {
+        '(' => ')',
+        '[' => ']',
+        _ => '}',
+    }
+}
+
+/// Source with its delimiters balanced, and how to find the original
+/// positions again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repair {
+    pub src: String,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Original char offset of each inserted closer and where it ended up
+    /// in `src`, in order
+    insertions: Vec<(usize, Range<usize>)>,
+}
+
+impl Repair {
+    /// Maps a char offset in the repaired source back to the original.
+    /// Offsets inside an inserted closer map to where it was inserted.
+    pub fn original_offset(&self, pos: usize) -> usize {
+        let before = self.insertions.partition_point(|(_, r)| r.start <= pos);
+        match before.checked_sub(1).map(|i| &self.insertions[i]) {
+            None => pos,
+            Some((at, r)) if pos < r.end => *at,
+            Some((at, r)) => at + (pos - r.end),
+        }
+    }
+
+    pub fn original_span(&self, span: &Span) -> Span {
+        Span::new(
+            span.file,
+            self.original_offset(span.start())..self.original_offset(span.end()),
+        )
+    }
+}
+
+/// Checks that every delimiter in `src` is matched. Strings, comments and
+/// char literals are skipped, so `"("` and `#\(` don't count.
+pub fn balance(file: FileId, src: &str) -> Repair {
+    let chars: Vec<char> = src.chars().collect();
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(
+            chars
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c == '\n')
+                .map(|(i, _)| i + 1),
+        )
+        .collect();
+
+    // Matching by indentation blames the right opener when one inside
+    // another is missing its closer, but it can misread code that is
+    // balanced yet oddly indented, so it only wins if it needs no more
+    // repairs
+    let plain = scan(file, &chars, &line_starts, false);
+    let Scan {
+        mut diagnostics,
+        mut insertions,
+        replaced,
+    } = match plain.diagnostics.is_empty() {
+        true => plain,
+        false => {
+            let by_indent = scan(file, &chars, &line_starts, true);
+            match by_indent.diagnostics.len() <= plain.diagnostics.len() {
+                true => by_indent,
+                false => plain,
+            }
+        }
+    };
+
+    insertions.sort_by_key(|(at, _)| *at);
+    diagnostics.sort_by_key(|d| d.span.start());
+
+    // Both are in order, so they're walked alongside the source
+    let mut repaired = String::with_capacity(src.len());
+    let mut len = 0;
+    let mut inserted = Vec::with_capacity(insertions.len());
+    let mut pending = insertions.into_iter().peekable();
+    let mut replaced = replaced.into_iter().peekable();
+    for i in 0..=chars.len() {
+        while let Some((at, text)) = pending.next_if(|(at, _)| *at == i) {
+            let n = text.chars().count();
+            inserted.push((at, len..len + n));
+            repaired.push_str(&text);
+            len += n;
+        }
+        let Some(c) = chars.get(i) else { break };
+        repaired.push(match replaced.next_if_eq(&i) {
+            Some(_) => ' ',
+            None => *c,
+        });
+        len += 1;
+    }
+
+    Repair {
+        src: repaired,
+        diagnostics,
+        insertions: inserted,
+    }
+}
+
+/// One reading of `chars`. A closer matches the innermost opener of its
+/// kind, or `by_indent`, the innermost one it isn't indented out of.
+fn scan(file: FileId, chars: &[char], line_starts: &[usize], by_indent: bool) -> Scan {
+    let line_of = |pos: usize| line_starts.partition_point(|start| *start <= pos);
+
+    let mut diagnostics = vec![];
+    let mut insertions = vec![];
+    let mut replaced = vec![];
+    let mut opens: Vec<Open> = vec![];
+    // By the open's id, the start of the first line after it that is
+    // indented no deeper than its own, which is probably where it should
+    // have been closed
+    let mut dedents: Vec<Option<usize>> = vec![];
+    // The ids of the opens without one yet, by their indent
+    let mut undedented: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    let mut indent = 0;
+    let mut state = State::Code;
+
+    let unclosed = |o: Open,
+                    at: usize,
+                    dedents: &[Option<usize>],
+                    diagnostics: &mut Vec<Diagnostic>,
+                    insertions: &mut Vec<(usize, String)>| {
+        let mut d = Diagnostic::new(
+            format!("unclosed `{}`", o.delimiter),
+            Span::new(file, o.pos..o.pos + 1),
+        )
+        .with_code(error_codes::UNCLOSED_DELIMITER);
+        let at = match dedents[o.id] {
+            Some(line) if line < at => {
+                d = d.with_note(format!(
+                    "`{}` is probably missing before line {}, which is indented no deeper",
+                    closer(o.delimiter),
+                    line_of(line)
+                ));
+                line
+            }
+            _ => at,
+        };
+        diagnostics.push(d);
+        insertions.push((at, closer(o.delimiter).to_string()));
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if (i == 0 || chars[i - 1] == '\n') && state == State::Code {
+            indent = chars[i..]
+                .iter()
+                .take_while(|c| **c == ' ' || **c == '\t')
+                .count();
+            let first = chars.get(i + indent).copied();
+            if !matches!(first, None | Some('\n' | ';' | ')' | ']' | '}')) {
+                for id in undedented.split_off(&indent).into_values().flatten() {
+                    dedents[id] = Some(i);
+                }
+            }
+        }
+
+        match (state, c) {
+            (State::Code, '"') => state = State::String,
+            (State::Code, ';') => state = State::Line,
+            (State::Code, '#') if next == Some('|') => {
+                state = State::Block(1);
+                i += 1;
+            }
+            // A char literal, which can be #\( or #\"
+            (State::Code, '#') if next == Some('\\') => i += 2,
+            (State::Code, '(' | '[' | '{') => {
+                undedented.entry(indent).or_default().push(dedents.len());
+                opens.push(Open {
+                    delimiter: c,
+                    pos: i,
+                    id: dedents.len(),
+                });
+                dedents.push(None);
+            }
+            (State::Code, ')' | ']' | '}') => {
+                let kind = |o: &Open| closer(o.delimiter) == c;
+                let mut matching = opens.iter().rposition(kind);
+                // One already indented out of is taken to be closed there,
+                // if there's an enclosing one to close instead
+                while let Some(m) =
+                    matching.filter(|m| by_indent && dedents[opens[*m].id].is_some())
+                {
+                    match opens[..m].iter().rposition(kind) {
+                        Some(enclosing) => matching = Some(enclosing),
+                        None => break,
+                    }
+                }
+                match matching {
+                    Some(matching) => {
+                        while opens.len() > matching + 1 {
+                            let o = opens.pop().unwrap();
+                            unclosed(o, i, &dedents, &mut diagnostics, &mut insertions);
+                        }
+                        opens.pop();
+                    }
+                    None => {
+                        diagnostics.push(
+                            Diagnostic::new(
+                                format!("unexpected `{c}` with nothing to close"),
+                                Span::new(file, i..i + 1),
+                            )
+                            .with_code(error_codes::UNMATCHED_DELIMITER),
+                        );
+                        replaced.push(i);
+                    }
+                }
+            }
+            (State::String, '\\') => i += 1,
+            (State::String, '"') => state = State::Code,
+            (State::Line, '\n') => state = State::Code,
+            (State::Block(n), '#') if next == Some('|') => {
+                state = State::Block(n + 1);
+                i += 1;
+            }
+            (State::Block(n), '|') if next == Some('#') => {
+                state = if n == 1 {
+                    State::Code
+                } else {
+                    State::Block(n - 1)
+                };
+                i += 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    while let Some(o) = opens.pop() {
+        unclosed(o, chars.len(), &dedents, &mut diagnostics, &mut insertions);
+    }
+    // An unterminated line comment would swallow a closer at the very end
+    for (at, text) in insertions.iter_mut() {
+        if *at == chars.len() && state == State::Line {
+            text.insert(0, '\n');
+        }
+    }
+
+    Scan {
+        diagnostics,
+        insertions,
+        replaced,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_balanced() {
+        let src = "(a [b {c}] \"(\" #\\( ; (\n #| ( |# d)";
+        let r = balance(FileId::default(), src);
+        assert_eq!(r.src, src);
+        assert!(r.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_unclosed() {
+        let src = "($x\n  ^x inc\n(1) $f\n";
+        let r = balance(FileId::default(), src);
+        assert_eq!(r.src, "($x\n  ^x inc\n)(1) $f\n");
+        assert_eq!(r.diagnostics.len(), 1);
+        assert_eq!(r.diagnostics[0].message, "unclosed `(`");
+        assert_eq!(r.diagnostics[0].span.range, 0..1);
+        assert_eq!(
+            r.diagnostics[0].notes,
+            vec!["`)` is probably missing before line 3, which is indented no deeper"]
+        );
+
+        assert_eq!(r.original_offset(12), 12);
+        assert_eq!(r.original_offset(13), 13);
+        assert_eq!(r.original_offset(14), 13);
+
+        // Nothing to go on, so it's closed at the end
+        let r = balance(FileId::default(), "(a (b) ; c");
+        assert_eq!(r.src, "(a (b) ; c\n)");
+        assert_eq!(r.original_offset(12), 10);
+
+        // The inner one is missing its closer, not the outer one
+        let r = balance(FileId::default(), "[a (b]");
+        assert_eq!(r.src, "[a (b)]");
+        assert_eq!(r.diagnostics[0].span.range, 3..4);
+
+        let r = balance(FileId::default(), "(a (b");
+        assert_eq!(r.src, "(a (b))");
+        let offsets: Vec<usize> = (4..8).map(|pos| r.original_offset(pos)).collect();
+        assert_eq!(offsets, vec![4, 5, 5, 5]);
+
+        // `qux)` closes the `(foo` it's indented like, not `(bar`
+        let r = balance(FileId::default(), "(foo\n  (bar baz\n  qux)\n(next)");
+        assert_eq!(r.src, "(foo\n  (bar baz\n)  qux)\n(next)");
+        assert_eq!(r.diagnostics.len(), 1);
+        assert_eq!(r.diagnostics[0].span.range, 7..8);
+        assert_eq!(
+            r.diagnostics[0].notes,
+            vec!["`)` is probably missing before line 3, which is indented no deeper"]
+        );
+
+        // Balanced but oddly indented code is left alone
+        let src = "(a\n  (b\n  c)\n)";
+        assert_eq!(balance(FileId::default(), src).src, src);
+
+        let n = 100_000;
+        let r = balance(FileId::default(), &"(\n".repeat(n));
+        assert_eq!(r.diagnostics.len(), n);
+        assert_eq!(r.src, "(\n".to_string() + &")(\n".repeat(n - 1) + ")");
+        assert_eq!(
+            r.diagnostics[n - 2].notes,
+            vec![format!(
+                "`)` is probably missing before line {n}, which is indented no deeper"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_unmatched() {
+        let r = balance(FileId::default(), "a) (b))");
+        assert_eq!(r.src, "a  (b) ");
+        assert_eq!(
+            r.diagnostics
+                .iter()
+                .map(|d| d.span.range.clone())
+                .collect::<Vec<_>>(),
+            vec![1..2, 6..7]
+        );
+
+        let n = 100_000;
+        let r = balance(
+            FileId::default(),
+            &format!("{}{}", ")".repeat(n), "(".repeat(n)),
+        );
+        assert_eq!(r.src, " ".repeat(n) + &"(".repeat(n) + &")".repeat(n));
+        assert_eq!(r.original_offset(3 * n), 2 * n);
+    }
+}
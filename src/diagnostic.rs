@@ -0,0 +1,228 @@
+use chumsky::error::{Simple, SimpleReason};
+
+use crate::{
+    error_codes::{self, ErrorCode},
+    parser::{FileId, Span},
+    source_map::SourceMap,
+};
+
+/// An error about some span of source, rendered with the offending line and
+/// a caret under the span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: Option<ErrorCode>,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+}
+
+/// Describes a token that was expected. Errors only record the
+/// characters that could have come next, so past the punctuation that has
+/// to be exact, what they start is named after what was being parsed.
+fn describe_expected(c: &Option<char>, label: Option<&str>) -> Option<String> {
+    match (c, label) {
+        (None, _) => Some("end of input".to_string()),
+        (Some(';'), _) => None,
+        (Some(c @ ('(' | ')' | '[' | ']' | '{' | '}' | '"' | '|')), _) => Some(format!("`{c}`")),
+        // Inside a group anything can come next
+        (Some(_), None | Some("expr" | "thunk" | "list" | "map" | "quotation")) => {
+            Some("an expression".to_string())
+        }
+        (Some(_), Some(label)) => Some(with_article(label)),
+    }
+}
+
+fn with_article(label: &str) -> String {
+    match label.chars().next() {
+        Some('a' | 'e' | 'i' | 'o' | 'u') => format!("an {label}"),
+        _ => format!("a {label}"),
+    }
+}
+
+fn describe_found(c: Option<&char>) -> String {
+    match c {
+        None => "end of input".to_string(),
+        Some(c) => format!("`{}`", c.escape_default()),
+    }
+}
+
+/// `a`, `a or b`, `a, b or c`
+fn one_of(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.clone(),
+        [init @ .., last] => format!("{} or {}", init.join(", "), last),
+    }
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            code: None,
+            message: message.into(),
+            span,
+            notes: vec![],
+        }
+    }
+
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn from_parse_error(file: FileId, e: &Simple<char>) -> Self {
+        let message = match e.reason() {
+            SimpleReason::Custom(m) => m.clone(),
+            SimpleReason::Unclosed { delimiter, .. } => {
+                format!("unclosed `{delimiter}`")
+            }
+            SimpleReason::Unexpected => {
+                let mut expected: Vec<String> = e
+                    .expected()
+                    .filter_map(|c| describe_expected(c, e.label()))
+                    .collect();
+                expected.sort();
+                expected.dedup();
+                if expected.is_empty() {
+                    expected.extend(e.label().filter(|l| *l != "expr").map(with_article));
+                }
+                if expected.is_empty() {
+                    format!("unexpected {}", describe_found(e.found()))
+                } else {
+                    format!(
+                        "expected {}, found {}",
+                        one_of(&expected),
+                        describe_found(e.found())
+                    )
+                }
+            }
+        };
+
+        let code = match e.reason() {
+            SimpleReason::Custom(_) => error_codes::INVALID_LITERAL,
+            _ => error_codes::UNEXPECTED_INPUT,
+        };
+        let d = Diagnostic::new(message, Span::new(file, e.span())).with_code(code);
+        match e.label() {
+            // Unless the message already says what was expected
+            Some(label) if label != "expr" && e.expected().next().is_some() => {
+                d.with_note(format!("while parsing {label}"))
+            }
+            _ => d,
+        }
+    }
+
+    /// Renders like
+    ///
+    /// ```text
+    /// error[F0001]: expected `)` or an expression, found end of input
+    ///  --> main.fpy:2:1
+    ///   |
+    /// 2 | (1 2
+    ///   |     ^
+    /// ```
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = match self.code {
+            Some(code) => format!("error[{}]: {}\n", code, self.message),
+            None => format!("error: {}\n", self.message),
+        };
+
+        let (file, loc) = match (sources.get(self.span.file), sources.location(&self.span)) {
+            (Some(file), Some(loc)) => (file, loc),
+            _ => {
+                for n in self.notes.iter() {
+                    out.push_str(&format!("  = note: {n}\n"));
+                }
+                return out;
+            }
+        };
+
+        let line = file.line(loc.line).unwrap_or("");
+        let gutter = " ".repeat(loc.line.to_string().len());
+
+        // Keep tabs so the caret lines up under the span
+        let indent: String = line
+            .chars()
+            .take(loc.col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = (self.span.end().saturating_sub(self.span.start()))
+            .min(line.chars().count().saturating_sub(loc.col - 1))
+            .max(1);
+
+        out.push_str(&format!("{gutter}--> {loc}\n"));
+        out.push_str(&format!("{gutter} |\n"));
+        out.push_str(&format!("{} | {}\n", loc.line, line));
+        out.push_str(&format!("{gutter} | {indent}{}\n", "^".repeat(width)));
+        for n in self.notes.iter() {
+            out.push_str(&format!("{gutter} = note: {n}\n"));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse_file;
+
+    fn parse_diagnostics(src: &str) -> Vec<String> {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.fpy", src);
+        let (_, diagnostics) = parse_file(file, src);
+        diagnostics.iter().map(|d| d.render(&sources)).collect()
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            parse_diagnostics("1 2 +\n\t' 1"),
+            vec!["error[F0001]: expected `(`, `[` or `{`, found `1`\n \
+                 --> test.fpy:2:4\n  \
+                 |\n\
+                 2 | \t' 1\n  \
+                 | \t  ^\n  \
+                 = note: while parsing quotation\n"
+                .to_string()]
+        );
+
+        assert_eq!(
+            parse_diagnostics("foo 99999999999999999999 bar"),
+            vec![
+                "error[F0002]: integer literal 99999999999999999999 doesn't fit in 64 bits\n \
+                 --> test.fpy:1:5\n  \
+                 |\n\
+                 1 | foo 99999999999999999999 bar\n  \
+                 |     ^^^^^^^^^^^^^^^^^^^^\n"
+                    .to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expected() {
+        let messages = |src: &str| -> Vec<String> {
+            parse_diagnostics(src)
+                .iter()
+                .map(|d| d.lines().next().unwrap().to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            messages("$(x) ^ 1"),
+            vec![
+                "error[F0001]: expected an atom, found `(`",
+                "error[F0001]: expected an atom, found ` `",
+            ]
+        );
+        assert_eq!(
+            messages("1 #| 2"),
+            vec!["error[F0001]: expected `|`, found end of input"]
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! Stable identifiers for every error the parser, compiler and evaluator
+//! can report, so docs and log scrapers don't depend on message wording.
+//! Codes are never reused or renumbered: parse errors are `F00xx`, pragma
+//! and compiler errors `F01xx` and evaluation errors `F02xx`.
+
+use std::{fmt::Display, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ErrorCode(pub u16);
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("F{:04}", self.0))
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = String;
+
+    /// Takes `F0201` or `f0201`, or just the number.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix(['F', 'f']).unwrap_or(s);
+        digits
+            .parse()
+            .map(ErrorCode)
+            .map_err(|_| format!("{s} is not an error code, they look like F0001"))
+    }
+}
+
+pub const UNEXPECTED_INPUT: ErrorCode = ErrorCode(1);
+pub const INVALID_LITERAL: ErrorCode = ErrorCode(2);
+pub const UNCLOSED_DELIMITER: ErrorCode = ErrorCode(3);
+pub const UNMATCHED_DELIMITER: ErrorCode = ErrorCode(4);
+
+pub const UNSUPPORTED_VERSION: ErrorCode = ErrorCode(101);
+pub const UNKNOWN_EXTENSION: ErrorCode = ErrorCode(102);
+
+pub const UNBOUND: ErrorCode = ErrorCode(201);
+pub const INVALID_APPLY: ErrorCode = ErrorCode(202);
+pub const POP_EMPTY: ErrorCode = ErrorCode(203);
+pub const TYPE_MISMATCH: ErrorCode = ErrorCode(204);
+pub const BARE_QUOTE: ErrorCode = ErrorCode(205);
+pub const DIVIDE_BY_ZERO: ErrorCode = ErrorCode(206);
+pub const NOT_FINITE: ErrorCode = ErrorCode(207);
+pub const INDEX_OUT_OF_BOUNDS: ErrorCode = ErrorCode(208);
+pub const INVALID_KEY: ErrorCode = ErrorCode(209);
+pub const KEY_NOT_FOUND: ErrorCode = ErrorCode(210);
+pub const MISSING_MAP_VALUE: ErrorCode = ErrorCode(211);
+pub const INVALID_RANGE: ErrorCode = ErrorCode(212);
+pub const INVALID_CHAR: ErrorCode = ErrorCode(213);
+pub const NOT_A_BYTE: ErrorCode = ErrorCode(214);
+pub const NOT_A_NAME: ErrorCode = ErrorCode(215);
+pub const STACK_UNDERFLOW: ErrorCode = ErrorCode(216);
+pub const OUT_OF_FUEL: ErrorCode = ErrorCode(217);
+pub const STACK_OVERFLOW: ErrorCode = ErrorCode(218);
+pub const TOO_MANY_FRAMES: ErrorCode = ErrorCode(219);
+pub const ENV_TOO_LARGE: ErrorCode = ErrorCode(220);
+pub const CANCELLED: ErrorCode = ErrorCode(221);
+
+/// The long form of an error, for `frospy explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Explanation {
+    pub code: ErrorCode,
+    pub title: &'static str,
+    pub text: &'static str,
+    /// A minimal program that reports the error
+    pub example: &'static str,
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{}: {}\n\n{}\n\nExample:\n\n",
+            self.code, self.title, self.text
+        ))?;
+        for line in self.example.lines() {
+            f.write_fmt(format_args!("    {}\n", line))?;
+        }
+        Ok(())
+    }
+}
+
+pub const EXPLANATIONS: &[Explanation] = &[
+    Explanation {
+        code: UNEXPECTED_INPUT,
+        title: "unexpected input",
+        text: "The parser found a character that can't come next, or ran out of input \
+               in the middle of an expression, like a string without its closing quote. \
+               The message lists what could have come instead.",
+        example: "\"unterminated",
+    },
+    Explanation {
+        code: INVALID_LITERAL,
+        title: "invalid literal",
+        text: "Something that looks like a literal doesn't describe a value: an \
+               integer that doesn't fit in 64 bits, a float that overflows, an unknown \
+               character name or escape, a non-ASCII character in a byte string, or a \
+               pragma without a version.",
+        example: "99999999999999999999",
+    },
+    Explanation {
+        code: UNCLOSED_DELIMITER,
+        title: "unclosed delimiter",
+        text: "A `(`, `[` or `{` is never closed. When a later line is indented no \
+               deeper than the line the delimiter is on, that is probably where the \
+               closer belongs, and the error says so. The rest of the file is still \
+               checked as if it were there.",
+        example: "($x\n  ^x inc\n) $f\n(1 2 +\n3 f",
+    },
+    Explanation {
+        code: UNMATCHED_DELIMITER,
+        title: "unmatched delimiter",
+        text: "A `)`, `]` or `}` doesn't close anything, either because there are \
+               more closers than openers or because it is the wrong kind, like the \
+               `]` in `(1 2]`.",
+        example: "1 2 +)",
+    },
+    Explanation {
+        code: UNSUPPORTED_VERSION,
+        title: "unsupported language version",
+        text: "The `#frospy` pragma at the top of a file asks for a version of the \
+               language this implementation doesn't understand.",
+        example: "#frospy 99\n1 2 +",
+    },
+    Explanation {
+        code: UNKNOWN_EXTENSION,
+        title: "unknown language extension",
+        text: "The `#frospy` pragma names an extension this implementation doesn't \
+               have. Running without it could silently change what the program means, \
+               so it is an error.",
+        example: "#frospy 1 macros\n1 2 +",
+    },
+    Explanation {
+        code: UNBOUND,
+        title: "unbound name",
+        text: "An atom was evaluated, but neither the program nor the builtins bind \
+               that name in the current environment. Names are bound with `$name`, \
+               which pops the top of the stack.",
+        example: "1 2 plus",
+    },
+    Explanation {
+        code: INVALID_APPLY,
+        title: "invalid apply",
+        text: "A value that can't be called, like an integer, was forced. Only thunks \
+               and builtins can be forced.",
+        example: "1 force",
+    },
+    Explanation {
+        code: POP_EMPTY,
+        title: "pop from empty stack",
+        text: "An operation needed a value from the stack, but the stack was empty.",
+        example: "1 +",
+    },
+    Explanation {
+        code: TYPE_MISMATCH,
+        title: "type mismatch",
+        text: "An operation got a value of the wrong type, like adding a string to an \
+               integer. The message names the type it expected and the one it got.",
+        example: "1 \"two\" +",
+    },
+    Explanation {
+        code: BARE_QUOTE,
+        title: "bare quote",
+        text: "`quote` quotes the expression after it, but there wasn't one.",
+        example: "(1 quote) force",
+    },
+    Explanation {
+        code: DIVIDE_BY_ZERO,
+        title: "division by zero",
+        text: "An integer `/` or `%` had zero on the right. Float division by zero \
+               gives an infinity instead.",
+        example: "1 0 /",
+    },
+    Explanation {
+        code: NOT_FINITE,
+        title: "not a finite float",
+        text: "A float that is infinite or not a number was converted to an integer.",
+        example: "#inf truncate",
+    },
+    Explanation {
+        code: INDEX_OUT_OF_BOUNDS,
+        title: "index out of bounds",
+        text: "`nth` or `uncons` reached past the end of a list, quotation or byte \
+               string.",
+        example: "[1 2] 2 nth",
+    },
+    Explanation {
+        code: INVALID_KEY,
+        title: "invalid map key",
+        text: "Maps are keyed by integers, atoms and strings. Other values, like \
+               floats and thunks, can't be keys.",
+        example: "{ 1.5 'a }",
+    },
+    Explanation {
+        code: KEY_NOT_FOUND,
+        title: "key not found",
+        text: "`get` looked up a key that isn't in the map. `contains?` checks first.",
+        example: "{ 'a 1 } 'b get",
+    },
+    Explanation {
+        code: MISSING_MAP_VALUE,
+        title: "missing map value",
+        text: "A map literal alternates keys and values, but it had an odd number of \
+               elements, so the last key has no value.",
+        example: "{ 'a 1 'b }",
+    },
+    Explanation {
+        code: INVALID_RANGE,
+        title: "invalid range",
+        text: "`slice` was given a start after its end, or an end past the length of \
+               the list.",
+        example: "[1 2 3] 1 5 slice",
+    },
+    Explanation {
+        code: INVALID_CHAR,
+        title: "invalid character",
+        text: "`integer->char` was given a number that isn't a Unicode scalar value, \
+               like a surrogate.",
+        example: "55296 integer->char",
+    },
+    Explanation {
+        code: NOT_A_BYTE,
+        title: "not a byte",
+        text: "`list->bytes` was given a list with something other than an integer \
+               from 0 to 255.",
+        example: "[1 256] list->bytes",
+    },
+    Explanation {
+        code: NOT_A_NAME,
+        title: "not a name",
+        text: "`pop` or `push` was given something other than an atom as the name to \
+               bind or look up. `$name` and `^name` always give them an atom, so this \
+               only comes from calling them by hand.",
+        example: "1 2 pop",
+    },
+    Explanation {
+        code: STACK_UNDERFLOW,
+        title: "stack underflow",
+        text: "An operation needed more values on the stack than there are, like \
+               `cswap`, which takes two values and a condition, with only one value \
+               under the condition.",
+        example: "1 't cswap",
+    },
+    Explanation {
+        code: OUT_OF_FUEL,
+        title: "out of fuel",
+        text: "The program ran for more steps than `--fuel` allows, which usually \
+               means it loops forever. The example never stops, and is run with a \
+               fuel of 10000.",
+        example: "($f ^f ^f force) $f\n^f ^f force",
+    },
+    Explanation {
+        code: STACK_OVERFLOW,
+        title: "stack overflow",
+        text: "The data stack grew past `--max-stack` values. The example pushes a \
+               value every time around its loop, and is run with a maximum of 1000.",
+        example: "($f 1 ^f ^f force) $f\n^f ^f force",
+    },
+    Explanation {
+        code: TOO_MANY_FRAMES,
+        title: "too many frames",
+        text: "More thunks were being forced at once than `--max-frames` allows. A \
+               force that is the last thing a thunk does replaces its frame, so only \
+               recursion that isn't in tail position, like in the example, grows them. \
+               The example is run with a maximum of 1000.",
+        example: "($f ^f ^f force 1) $f\n^f ^f force",
+    },
+    Explanation {
+        code: ENV_TOO_LARGE,
+        title: "environment too large",
+        text: "An environment has more bindings than `--max-env` allows, not counting \
+               the builtins. The example is run with a maximum of 8.",
+        example: "0 $a 0 $b 0 $c 0 $d 0 $e 0 $f 0 $g 0 $h 0 $i",
+    },
+    Explanation {
+        code: CANCELLED,
+        title: "cancelled",
+        text: "The evaluation was stopped from outside, by Ctrl-C or by whatever is \
+               running the evaluator. The stacktrace shows where it was. The example \
+               never stops on its own.",
+        example: "($f ^f ^f force) $f\n^f ^f force",
+    },
+];
+
+pub fn explain(code: ErrorCode) -> Option<&'static Explanation> {
+    EXPLANATIONS.iter().find(|e| e.code == code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(UNBOUND.to_string(), "F0201");
+        assert_eq!("F0201".parse(), Ok(UNBOUND));
+        assert_eq!("f1".parse(), Ok(UNEXPECTED_INPUT));
+        assert!("E0001".parse::<ErrorCode>().is_err());
+
+        let mut codes: Vec<_> = EXPLANATIONS.iter().map(|e| e.code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), EXPLANATIONS.len());
+
+        assert_eq!(
+            explain(DIVIDE_BY_ZERO).unwrap().to_string(),
+            "F0206: division by zero\n\n\
+             An integer `/` or `%` had zero on the right. Float division by zero gives \
+             an infinity instead.\n\nExample:\n\n    1 0 /\n"
+        );
+        assert_eq!(explain(ErrorCode(9999)), None);
+
+        assert_eq!(
+            explain(STACK_UNDERFLOW).unwrap().to_string(),
+            "F0216: stack underflow\n\n\
+             An operation needed more values on the stack than there are, like `cswap`, \
+             which takes two values and a condition, with only one value under the \
+             condition.\n\nExample:\n\n    1 't cswap\n"
+        );
+    }
+
+    #[test]
+    fn test_examples() {
+        use crate::{eval, parser, trace::Silent};
+
+        // What the explanations of the limits say their examples run with
+        let limits = eval::EvalLimits {
+            fuel: Some(10_000),
+            max_stack: Some(1000),
+            max_frames: Some(1000),
+            max_env: Some(8),
+        };
+        for e in EXPLANATIONS.iter() {
+            let opts = eval::EvalOptions {
+                limits: limits.clone(),
+                ..Default::default()
+            };
+            // As if Ctrl-C had been pressed right away
+            if e.code == CANCELLED {
+                opts.cancel.cancel();
+            }
+            let code = match parser::parse_file(Default::default(), e.example) {
+                (ast, ds) if ds.is_empty() => eval::eval_with(&ast, opts, &mut Silent)
+                    .map(|_| None)
+                    .unwrap_or_else(|s| Some(s.error.code())),
+                (_, ds) => ds[0].code,
+            };
+            assert_eq!(code, Some(e.code), "example for {}", e.code);
+        }
+    }
+}
@@ -1,75 +1,363 @@
 use std::fmt::Display;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use rpds::HashTrieMap;
+use rpds::{HashTrieMap, Vector};
 use thiserror::Error;
 
-use crate::parser::{Expr, Span};
+use crate::error_codes::{self, ErrorCode};
+use crate::header::bigint::BigInt;
+use crate::parser::{self, Expr, PragmaError, Span};
+use crate::source_map::SourceMap;
+use crate::trace::{Observer, Silent};
 
 type Env = HashTrieMap<String, Value>;
 
+type Map = HashTrieMap<Key, Value>;
+
 type BuiltInFn = fn(&mut Env, &mut Vec<Value>) -> Result<(), EvalStacktrace>;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Integer(i64),
+    BigInt(BigInt),
+    Float(f64),
     Atom(String),
+    String(String),
+    Char(char),
+    Bytes(Vec<u8>),
+    Quotation(Vec<Expr>),
+    List(Vector<Value>),
+    Map(Map),
     Thunk { env: Env, exprs: Vec<Expr> },
     BuiltIn(&'static str, Box<BuiltInFn>),
 }
 
 impl Value {
-    fn get_name(&self) -> Option<&str> {
+    fn get_name(&self) -> Result<&str, EvalError> {
         match self {
-            Value::Atom(s) => Some(s),
-            _ => None,
+            Value::Atom(s) => Ok(s),
+            v => Err(EvalError::NotAName(v.type_name().to_string())),
+        }
+    }
+
+    /// Quoted lists and maps can nest as deeply as the reader allows, so
+    /// their elements are converted on a stack of its own.
+    fn from_quoted_expr(e: &Expr) -> Result<Self, EvalError> {
+        enum Todo<'a> {
+            Quote(&'a Expr),
+            /// Takes its elements off the end of `done`
+            Assemble(&'a Expr),
+        }
+
+        let mut todo = vec![Todo::Quote(e)];
+        let mut done: Vec<Value> = vec![];
+        while let Some(t) = todo.pop() {
+            let v = match t {
+                Todo::Quote(e @ (Expr::List(es, _) | Expr::Map(es, _))) => {
+                    todo.push(Todo::Assemble(e));
+                    todo.extend(es.iter().rev().map(Todo::Quote));
+                    continue;
+                }
+                Todo::Quote(e) => match e {
+                    Expr::Integer(i, _) => Value::Integer(*i),
+                    Expr::Float(x, _) => Value::Float(*x),
+                    Expr::Atom(a, _) => Value::Atom(a.to_string()),
+                    Expr::String(s, _) => Value::String(s.to_string()),
+                    Expr::Char(c, _) => Value::Char(*c),
+                    Expr::Bytes(b, _) => Value::Bytes(b.clone()),
+                    Expr::Thunk(es, _) => Value::Quotation(es.clone()),
+                    Expr::List(..) | Expr::Map(..) => unreachable!(),
+                    Expr::Pragma(..) => {
+                        return Err(EvalError::TypeMismatch(
+                            "quotable expression".to_string(),
+                            "pragma".to_string(),
+                        ))
+                    }
+                },
+                Todo::Assemble(e) => {
+                    let n = e.children().map_or(0, <[Expr]>::len);
+                    let items = done.split_off(done.len() - n);
+                    match e {
+                        Expr::Map(..) => Value::from_map_items(items)?,
+                        _ => Value::List(items.into_iter().collect()),
+                    }
+                }
+            };
+            done.push(v);
+        }
+        Ok(done.pop().unwrap())
+    }
+
+    /// Pairs up alternating keys and values, later keys winning.
+    fn from_map_items(items: Vec<Value>) -> Result<Self, EvalError> {
+        let mut map = Map::new();
+        let mut items = items.into_iter();
+        while let Some(k) = items.next() {
+            let k = Key::from_value(&k)?;
+            let v = items
+                .next()
+                .ok_or_else(|| EvalError::MissingMapValue(k.to_string()))?;
+            map.insert_mut(k, v);
         }
+        Ok(Value::Map(map))
     }
 
-    fn from_quoted_expr(e: &Expr) -> Self {
-        match e {
-            Expr::Integer(i, _) => Value::Integer(*i),
-            Expr::Atom(a, _) => Value::Atom(a.to_string()),
-            Expr::Thunk(_, _) => panic!("Can't get quote of thunk"),
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) | Value::BigInt(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Atom(_) => "atom",
+            Value::String(_) => "string",
+            Value::Char(_) => "char",
+            Value::Bytes(_) => "bytes",
+            Value::Quotation(_) => "quotation",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Thunk { .. } => "thunk",
+            Value::BuiltIn(_, _) => "builtin",
         }
     }
 
-    fn get_integer(&self) -> Result<i64, EvalError> {
+    fn get_bigint(&self) -> Result<BigInt, EvalError> {
         match self {
-            Value::Integer(i) => Ok(*i),
-            Value::Atom(_) => Err(EvalError::TypeMismatch(
+            Value::Integer(i) => Ok(BigInt::from_i64(*i)),
+            Value::BigInt(b) => Ok(b.clone()),
+            v => Err(EvalError::TypeMismatch(
                 "integer".to_string(),
-                "atom".to_string(),
+                v.type_name().to_string(),
             )),
-            Value::Thunk { .. } => Err(EvalError::TypeMismatch(
-                "thunk".to_string(),
-                "atom".to_string(),
+        }
+    }
+
+    fn get_float(&self) -> Result<f64, EvalError> {
+        match self {
+            Value::Integer(i) => Ok(*i as f64),
+            Value::BigInt(b) => Ok(b.to_f64()),
+            Value::Float(x) => Ok(*x),
+            v => Err(EvalError::TypeMismatch(
+                "number".to_string(),
+                v.type_name().to_string(),
+            )),
+        }
+    }
+
+    fn get_quotation(&self) -> Result<&[Expr], EvalError> {
+        match self {
+            Value::Quotation(exprs) => Ok(exprs),
+            v => Err(EvalError::TypeMismatch(
+                "quotation".to_string(),
+                v.type_name().to_string(),
+            )),
+        }
+    }
+
+    fn get_list(&self) -> Result<&Vector<Value>, EvalError> {
+        match self {
+            Value::List(items) => Ok(items),
+            v => Err(EvalError::TypeMismatch(
+                "list".to_string(),
+                v.type_name().to_string(),
             )),
-            Value::BuiltIn(_, _) => Err(EvalError::TypeMismatch(
-                "builtin".to_string(),
-                "atom".to_string(),
+        }
+    }
+
+    fn get_map(&self) -> Result<&Map, EvalError> {
+        match self {
+            Value::Map(map) => Ok(map),
+            v => Err(EvalError::TypeMismatch(
+                "map".to_string(),
+                v.type_name().to_string(),
             )),
         }
     }
+
+    /// An index as it was given, which errors show, and as a usize if it
+    /// is one.
+    fn get_index(&self) -> Result<(BigInt, Option<usize>), EvalError> {
+        let b = self.get_bigint()?;
+        let i = b.to_i64().and_then(|i| usize::try_from(i).ok());
+        Ok((b, i))
+    }
+
+    /// Integers are only big when they have to be.
+    fn from_bigint(b: BigInt) -> Self {
+        match b.to_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::BigInt(b),
+        }
+    }
 }
 
+/// Lists and maps can nest as deeply as the program builds them, so
+/// printing one doesn't recurse.
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Value::Integer(i) => f.write_fmt(format_args!("{}", i)),
-            Value::Atom(s) => f.write_str(s),
-            Value::Thunk { exprs, .. } => {
-                f.write_str("( ")?;
-                for e in exprs.iter() {
-                    f.write_fmt(format_args!("{} ", e))?;
+        enum Todo<'a> {
+            Value(&'a Value),
+            Key(&'a Key),
+            Str(&'static str),
+        }
+
+        let mut todo = vec![Todo::Value(self)];
+        while let Some(t) = todo.pop() {
+            let v = match t {
+                Todo::Value(v) => v,
+                Todo::Key(k) => {
+                    f.write_fmt(format_args!("{} ", k))?;
+                    continue;
+                }
+                Todo::Str(s) => {
+                    f.write_str(s)?;
+                    continue;
+                }
+            };
+            match v {
+                Value::Integer(i) => f.write_fmt(format_args!("{}", i))?,
+                Value::BigInt(b) => f.write_fmt(format_args!("{}", b))?,
+                Value::Float(x) => f.write_str(&parser::float_literal(*x))?,
+                Value::Atom(s) => f.write_str(s)?,
+                Value::String(s) => f.write_str(s)?,
+                Value::Char(c) => f.write_fmt(format_args!("{}", c))?,
+                Value::Bytes(b) => f.write_str(&parser::bytes_literal(b))?,
+                Value::Quotation(exprs) => {
+                    f.write_str("'( ")?;
+                    for e in exprs.iter() {
+                        f.write_fmt(format_args!("{} ", e))?;
+                    }
+                    f.write_str(")")?
+                }
+                Value::List(items) => {
+                    f.write_str("[ ")?;
+                    todo.push(Todo::Str("]"));
+                    for v in items.iter().rev() {
+                        todo.push(Todo::Str(" "));
+                        todo.push(Todo::Value(v));
+                    }
+                }
+                Value::Map(map) => {
+                    f.write_str("{ ")?;
+                    todo.push(Todo::Str("}"));
+                    for (k, v) in sorted_entries(map).into_iter().rev() {
+                        todo.push(Todo::Str(" "));
+                        todo.push(Todo::Value(v));
+                        todo.push(Todo::Key(k));
+                    }
+                }
+                Value::Thunk { exprs, .. } => {
+                    f.write_str("( ")?;
+                    for e in exprs.iter() {
+                        f.write_fmt(format_args!("{} ", e))?;
+                    }
+                    f.write_str(")")?
+                    // f.write_fmt(format_args!("<{}>", env))
+                }
+                Value::BuiltIn(n, _) => f.write_fmt(format_args!("*{}", n))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a list or map held when it was dropped.
+enum Nested {
+    List(Vector<Value>),
+    Map(Map),
+}
+
+thread_local! {
+    /// Set while a list or map is being dropped, for the ones nested in it
+    /// to wait on.
+    static DROPPING: std::cell::RefCell<Option<Vec<Nested>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Dropping a list or map drops the values in it, which can be lists and
+/// maps nested as deeply as the program built them. Those are handed to
+/// the outermost drop instead, which drops them one after another rather
+/// than recursing.
+impl Drop for Value {
+    fn drop(&mut self) {
+        let nested = match self {
+            Value::List(items) if !items.is_empty() => Nested::List(mem::take(items)),
+            Value::Map(map) if !map.is_empty() => Nested::Map(mem::take(map)),
+            _ => return,
+        };
+
+        let outermost = DROPPING.try_with(|d| {
+            let mut d = d.borrow_mut();
+            match d.as_mut() {
+                Some(waiting) => {
+                    waiting.push(nested);
+                    None
+                }
+                None => {
+                    *d = Some(vec![]);
+                    Some(nested)
                 }
-                f.write_str(")")
-                // f.write_fmt(format_args!("<{}>", env))
             }
-            Value::BuiltIn(n, _) => f.write_fmt(format_args!("*{}", n)),
+        });
+        // Only while the thread is exiting
+        let Ok(Some(mut next)) = outermost else {
+            return;
+        };
+        loop {
+            match next {
+                Nested::List(items) => drop(items),
+                Nested::Map(map) => drop(map),
+            }
+            match DROPPING.with(|d| d.borrow_mut().as_mut().and_then(Vec::pop)) {
+                Some(n) => next = n,
+                None => break,
+            }
+        }
+        DROPPING.with(|d| *d.borrow_mut() = None);
+    }
+}
+
+/// The values that can be map keys. Integers are kept big so that equal
+/// integers are equal keys whichever representation they had.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Key {
+    Integer(BigInt),
+    Atom(String),
+    String(String),
+}
+
+impl Key {
+    fn from_value(v: &Value) -> Result<Self, EvalError> {
+        match v {
+            Value::Integer(_) | Value::BigInt(_) => Ok(Key::Integer(v.get_bigint()?)),
+            Value::Atom(a) => Ok(Key::Atom(a.to_string())),
+            Value::String(s) => Ok(Key::String(s.to_string())),
+            v => Err(EvalError::InvalidKey(v.type_name().to_string())),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Key::Integer(b) => Value::from_bigint(b.clone()),
+            Key::Atom(a) => Value::Atom(a.to_string()),
+            Key::String(s) => Value::String(s.to_string()),
         }
     }
 }
 
+impl Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_value().fmt(f)
+    }
+}
+
+/// Map iteration order depends on hashing, so anything the program can
+/// observe goes in key order.
+fn sorted_entries(map: &Map) -> Vec<(&Key, &Value)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
 trait ResultSpanCtx<E> {
     fn to_stacktrace(self) -> E;
     fn with_span(self, s: Span) -> E;
@@ -85,15 +373,85 @@ pub struct EvalStacktrace {
 
 impl Display for EvalStacktrace {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("Error: {}", self.error))?;
+        f.write_fmt(format_args!("Error[{}]: {}", self.error.code(), self.error))?;
         f.write_str("Stacktrace:")?;
-        for s in self.stack.iter() {
-            f.write_fmt(format_args!("\t{:?}", s))?;
+        for (cycle, times) in fold_frames(&self.stack) {
+            for s in cycle.iter() {
+                f.write_fmt(format_args!("\t{:?}", s.range))?;
+            }
+            if times > 1 {
+                f.write_fmt(format_args!("\t(x{})", times))?;
+            }
         }
         Ok(())
     }
 }
 
+/// The longest cycle of frames `fold_frames` looks for.
+const MAX_CYCLE: usize = 8;
+
+/// Folds a cycle of frames that repeats back to back, like a loop through a
+/// Y-combinator leaves behind, into a single copy and a count.
+fn fold_frames(stack: &[Span]) -> Vec<(&[Span], usize)> {
+    let mut folded = vec![];
+    let mut i = 0;
+    while i < stack.len() {
+        let (mut len, mut times) = (1, 1);
+        for p in 1..=MAX_CYCLE.min((stack.len() - i) / 2) {
+            let cycle = &stack[i..i + p];
+            let k = stack[i..]
+                .chunks_exact(p)
+                .take_while(|c| *c == cycle)
+                .count();
+            if k > 1 && k * p > len * times {
+                (len, times) = (p, k);
+            }
+        }
+        folded.push((&stack[i..i + len], times));
+        i += len * times;
+    }
+    folded
+}
+
+impl EvalStacktrace {
+    /// The error and then one line per frame, innermost first, with where
+    /// it is and the start of its source.
+    pub fn render(&self, sources: &SourceMap) -> String {
+        const FRAGMENT_LEN: usize = 40;
+
+        let mut out = format!("error[{}]: {}\n", self.error.code(), self.error);
+        let mut idx = 0;
+        for (cycle, times) in fold_frames(&self.stack) {
+            for span in cycle.iter() {
+                let (loc, fragment) = match (sources.location(span), sources.get(span.file)) {
+                    (Some(loc), Some(file)) => (loc.to_string(), file.slice(span)),
+                    _ => (format!("{:?}", span.range), String::new()),
+                };
+                let line = fragment.lines().next().unwrap_or("").trim();
+                let fragment = if line.chars().count() > FRAGMENT_LEN || fragment.contains('\n') {
+                    format!(
+                        "{} ...",
+                        line.chars().take(FRAGMENT_LEN).collect::<String>()
+                    )
+                } else {
+                    line.to_string()
+                };
+                out.push_str(&format!("{idx:>4}: {loc:<20} {fragment}\n"));
+                idx += 1;
+            }
+            if times > 1 {
+                let what = match cycle.len() {
+                    1 => "frame".to_string(),
+                    n => format!("{n} frames"),
+                };
+                out.push_str(&format!("      {what} repeated {times} times\n"));
+                idx += cycle.len() * (times - 1);
+            }
+        }
+        out
+    }
+}
+
 impl<T> ResultSpanCtx<Result<T, EvalStacktrace>> for Result<T, EvalError> {
     fn with_span(self, s: Span) -> Result<T, EvalStacktrace> {
         self.map_err(|e| {
@@ -146,95 +504,427 @@ pub enum EvalError {
 
     #[error("Attempt to quote missing expr")]
     BareQuote,
+
+    #[error("Division by zero")]
+    DivideByZero,
+
+    #[error("Can't convert {} to an integer", parser::float_literal(*.0))]
+    NotFinite(f64),
+
+    #[error("Index {index} out of bounds for length {len}")]
+    IndexOutOfBounds { index: BigInt, len: usize },
+
+    #[error("Can't use type {0} as a map key")]
+    InvalidKey(String),
+
+    #[error("Key {0} not found in map")]
+    KeyNotFound(String),
+
+    #[error("Key {0} in map literal has no value")]
+    MissingMapValue(String),
+
+    #[error("Range {start}..{end} out of bounds for length {len}")]
+    InvalidRange {
+        start: BigInt,
+        end: BigInt,
+        len: usize,
+    },
+
+    #[error("{0} is not a valid character")]
+    InvalidChar(String),
+
+    #[error("{0} is not a byte")]
+    NotAByte(String),
+
+    #[error("Expected a name to bind or look up, got {0}")]
+    NotAName(String),
+
+    #[error("Needed {needed} values on the stack, but there are only {had}")]
+    StackUnderflow { needed: usize, had: usize },
+
+    #[error("Ran out of fuel after {0} steps")]
+    OutOfFuel(usize),
+
+    #[error("Stack grew past {0} values")]
+    StackOverflow(usize),
+
+    #[error("More than {0} frames deep")]
+    TooManyFrames(usize),
+
+    #[error("More than {0} bindings in one environment")]
+    EnvTooLarge(usize),
+
+    #[error("Cancelled")]
+    Cancelled,
+
+    #[error("{0}")]
+    Pragma(#[from] PragmaError),
 }
 
-struct EvalCtx<'a, 'b> {
-    exprs: &'a [Expr],
-    env: Env,
-    stack: &'b mut Vec<Value>,
-    tracing: bool,
-}
-
-fn apply_value(v: Value, env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
-    //println!("applying {}", v);
-    match v {
-        Value::Integer(_) => Err(EvalError::InvalidApply("integer".to_string())).to_stacktrace(),
-        Value::Atom(_) => Err(EvalError::InvalidApply("atom".to_string())).to_stacktrace(),
-        Value::Thunk { env, exprs } => {
-            let nec = EvalCtx {
-                env: env.clone(),
-                exprs: &exprs,
-                stack,
-                tracing: false,
-            };
-            nec.eval()
+impl EvalError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            EvalError::Unbound(_) => error_codes::UNBOUND,
+            EvalError::InvalidApply(_) => error_codes::INVALID_APPLY,
+            EvalError::PopEmpty => error_codes::POP_EMPTY,
+            EvalError::TypeMismatch(..) => error_codes::TYPE_MISMATCH,
+            EvalError::BareQuote => error_codes::BARE_QUOTE,
+            EvalError::DivideByZero => error_codes::DIVIDE_BY_ZERO,
+            EvalError::NotFinite(_) => error_codes::NOT_FINITE,
+            EvalError::IndexOutOfBounds { .. } => error_codes::INDEX_OUT_OF_BOUNDS,
+            EvalError::InvalidKey(_) => error_codes::INVALID_KEY,
+            EvalError::KeyNotFound(_) => error_codes::KEY_NOT_FOUND,
+            EvalError::MissingMapValue(_) => error_codes::MISSING_MAP_VALUE,
+            EvalError::InvalidRange { .. } => error_codes::INVALID_RANGE,
+            EvalError::InvalidChar(_) => error_codes::INVALID_CHAR,
+            EvalError::NotAByte(_) => error_codes::NOT_A_BYTE,
+            EvalError::NotAName(_) => error_codes::NOT_A_NAME,
+            EvalError::StackUnderflow { .. } => error_codes::STACK_UNDERFLOW,
+            EvalError::OutOfFuel(_) => error_codes::OUT_OF_FUEL,
+            EvalError::StackOverflow(_) => error_codes::STACK_OVERFLOW,
+            EvalError::TooManyFrames(_) => error_codes::TOO_MANY_FRAMES,
+            EvalError::EnvTooLarge(_) => error_codes::ENV_TOO_LARGE,
+            EvalError::Cancelled => error_codes::CANCELLED,
+            EvalError::Pragma(e) => e.code(),
         }
-        Value::BuiltIn(_, f) => f(env, stack).to_stacktrace(),
     }
 }
 
-impl EvalCtx<'_, '_> {
-    fn eval(self) -> Result<(), EvalStacktrace> {
-        let EvalCtx {
-            exprs,
-            mut env,
-            stack,
-            tracing,
-        } = self;
+/// Bounds on what a program may use, for running code that isn't trusted.
+/// `None`, the default, means no bound.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EvalLimits {
+    /// Steps to run for. A step is an expression or a frame returning, and
+    /// in compiled programs one run of a thunk body up to its next force.
+    pub fuel: Option<usize>,
+    /// Values on the data stack
+    pub max_stack: Option<usize>,
+    /// Frames, counting the top level, thunks being forced and list and
+    /// map literals being built. Compiled programs keep continuations on
+    /// the data stack instead, so there only literals count.
+    pub max_frames: Option<usize>,
+    /// Bindings in one environment, besides the builtins
+    pub max_env: Option<usize>,
+}
+
+/// Stops an evaluation from another thread, or a signal handler. Clones
+/// share the same flag, which the evaluator checks before every expression.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EvalOptions {
+    pub limits: EvalLimits,
+    pub cancel: CancelToken,
+}
+
+/// What a frame evaluates, which decides what happens when it's done.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameKind {
+    Thunk,
+    /// The elements of a list literal, which start at this height on the
+    /// stack
+    List(usize),
+    /// Like a list, but paired up into a map
+    Map(usize),
+}
+
+#[derive(Debug)]
+struct Frame {
+    exprs: Vec<Expr>,
+    idx: usize,
+    env: Env,
+    kind: FrameKind,
+    /// Where the thunk was forced or the literal is, for stacktraces. The
+    /// top-level frame has none.
+    span: Option<Span>,
+}
+
+impl Frame {
+    fn is_done(&self) -> bool {
+        self.idx >= self.exprs.len()
+    }
+}
 
-        let mut exs = exprs;
+/// The name `force` is bound to. Forcing a thunk needs a new frame, which
+/// a `BuiltInFn` can't push, so `EvalCtx::apply` recognizes it by name.
+const FORCE: &str = "force";
+
+/// The names `$name` and `^name` call, which the observer hears about.
+const POP: &str = "pop";
+const PUSH: &str = "push";
+
+/// Evaluates with its frames on the heap rather than the Rust stack, so
+/// deep recursion only costs memory, and a force in tail position replaces
+/// the current frame, so loops run in constant space.
+struct EvalCtx<'o> {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    limits: EvalLimits,
+    cancel: CancelToken,
+    observer: &'o mut dyn Observer,
+    steps: usize,
+    /// Bindings in the initial env, which `max_env` doesn't count
+    builtins: usize,
+}
+
+impl<'o> EvalCtx<'o> {
+    fn new(exprs: Vec<Expr>, env: Env, opts: EvalOptions, observer: &'o mut dyn Observer) -> Self {
+        EvalCtx {
+            stack: vec![],
+            builtins: env.size(),
+            frames: vec![Frame {
+                exprs,
+                idx: 0,
+                env,
+                kind: FrameKind::Thunk,
+                span: None,
+            }],
+            limits: opts.limits,
+            cancel: opts.cancel,
+            observer,
+            steps: 0,
+        }
+    }
 
+    fn run(&mut self) -> Result<(), EvalStacktrace> {
         loop {
-            if exs.is_empty() {
-                break;
+            match self.pump() {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(e) => {
+                    let e = self.unwind(e);
+                    self.observer.error(&e);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Adds the frames the error happened in to its stacktrace, innermost
+    /// first.
+    fn unwind(&self, mut e: EvalStacktrace) -> EvalStacktrace {
+        e.stack
+            .extend(self.frames.iter().rev().filter_map(|f| f.span.clone()));
+        e
+    }
+
+    fn check_limits(&self) -> Result<(), EvalError> {
+        let exceeds = |max: Option<usize>, n: usize| max.filter(|max| n > *max);
+        let l = &self.limits;
+
+        if let Some(max) = exceeds(l.max_stack, self.stack.len()) {
+            return Err(EvalError::StackOverflow(max));
+        }
+        if let Some(max) = exceeds(l.max_frames, self.frames.len()) {
+            return Err(EvalError::TooManyFrames(max));
+        }
+        if let Some(frame) = self.frames.last() {
+            let bindings = frame.env.size().saturating_sub(self.builtins);
+            if let Some(max) = exceeds(l.max_env, bindings) {
+                return Err(EvalError::EnvTooLarge(max));
+            }
+            // Only when there is another step to take
+            if let Some(max) = l.fuel.filter(|max| self.steps >= *max) {
+                return Err(EvalError::OutOfFuel(max));
             }
+        }
+        Ok(())
+    }
 
-            let e;
+    /// Evaluates one expression, or finishes the current frame. True once
+    /// there is nothing left to evaluate.
+    fn pump(&mut self) -> Result<bool, EvalStacktrace> {
+        self.check_limits().to_stacktrace()?;
+        let top = match self.frames.len() {
+            0 => return Ok(true),
+            n => n - 1,
+        };
+        self.steps += 1;
+        let frame = &mut self.frames[top];
+
+        // A frame runs its expressions once, so each is taken rather than
+        // cloned, which would copy every thunk nested in it
+        let mut e = match frame.exprs.get_mut(frame.idx) {
+            Some(e) => {
+                let span = e.get_span().clone();
+                mem::replace(e, Expr::Thunk(vec![], span))
+            }
+            None => {
+                let frame = self.frames.remove(top);
+                self.observer.ret(frame.span.as_ref());
+                self.finish(frame)?;
+                return Ok(false);
+            }
+        };
+        frame.idx += 1;
 
-            (e, exs) = exs.split_first().unwrap();
+        if self.cancel.is_cancelled() {
+            return Err(EvalError::Cancelled).with_span(e.get_span().clone());
+        }
 
-            if tracing {
-                print!("TRACE {:?}\t", e);
-                for v in stack.iter() {
-                    print!("{} ", v);
+        self.observer.instruction(&e, top + 1, &self.stack);
+
+        // Expr has a Drop impl, so the parts are taken rather than moved
+        match &mut e {
+            Expr::Integer(i, _) => self.stack.push(Value::Integer(*i)),
+            Expr::Float(x, _) => self.stack.push(Value::Float(*x)),
+            Expr::String(s, _) => self.stack.push(Value::String(mem::take(s))),
+            Expr::Char(c, _) => self.stack.push(Value::Char(*c)),
+            Expr::Bytes(b, _) => self.stack.push(Value::Bytes(mem::take(b))),
+            Expr::Atom(a, span) => match a.as_str() {
+                "quote" => {
+                    let qe = frame
+                        .exprs
+                        .get(frame.idx)
+                        .ok_or(EvalError::BareQuote)
+                        .with_span(span.clone())?;
+                    let v = Value::from_quoted_expr(qe).with_span(qe.get_span().clone())?;
+                    frame.idx += 1;
+                    self.stack.push(v);
                 }
-                println!();
+                a => {
+                    let v = frame
+                        .env
+                        .get(a)
+                        .ok_or_else(|| EvalError::Unbound(a.to_string()))
+                        .with_span(span.clone())?
+                        .clone();
+
+                    self.apply(top, v, span.clone())?;
+                }
+            },
+            Expr::Thunk(exprs, _) => {
+                let t = Value::Thunk {
+                    env: frame.env.clone(),
+                    exprs: mem::take(exprs),
+                };
+
+                self.stack.push(t);
+            }
+            Expr::List(exprs, span) => {
+                self.enter_literal(top, mem::take(exprs), span.clone(), FrameKind::List)
             }
+            Expr::Map(exprs, span) => {
+                self.enter_literal(top, mem::take(exprs), span.clone(), FrameKind::Map)
+            }
+            Expr::Pragma(p, span) => p.check().map_err(EvalError::from).with_span(span.clone())?,
+        }
+
+        Ok(false)
+    }
 
-            match e {
-                Expr::Integer(i, _) => stack.push(Value::Integer(*i)),
-                Expr::Atom(a, span) => match a.as_str() {
-                    "quote" => {
-                        let qe;
-                        (qe, exs) = exs
-                            .split_first()
-                            .ok_or(EvalError::BareQuote)
-                            .with_span(span.clone())?;
+    /// The elements of a literal are whatever they leave on the stack. Like
+    /// a thunk body, they get their own scope.
+    fn enter_literal(
+        &mut self,
+        top: usize,
+        exprs: Vec<Expr>,
+        span: Span,
+        kind: fn(usize) -> FrameKind,
+    ) {
+        let parent = &self.frames[top];
+        let frame = Frame {
+            exprs,
+            idx: 0,
+            env: parent.env.clone(),
+            kind: kind(self.stack.len()),
+            span: Some(span),
+        };
+        self.frames.push(frame);
+    }
 
-                        stack.push(Value::from_quoted_expr(qe));
+    /// Calls `v` from the frame at `top`. A thunk gets a frame of its own,
+    /// which replaces the caller's when the call is the last thing it does.
+    fn apply(&mut self, top: usize, mut v: Value, span: Span) -> Result<(), EvalStacktrace> {
+        loop {
+            match &mut v {
+                Value::BuiltIn(FORCE, _) => {
+                    v = self
+                        .stack
+                        .pop()
+                        .ok_or(EvalError::PopEmpty)
+                        .with_span(span.clone())?;
+                }
+                Value::Thunk { env, exprs } => {
+                    let caller = &mut self.frames[top];
+                    let tail = caller.is_done() && caller.kind == FrameKind::Thunk;
+                    self.observer.force(&span, tail);
+                    let callee = Frame {
+                        exprs: mem::take(exprs),
+                        idx: 0,
+                        env: mem::take(env),
+                        kind: FrameKind::Thunk,
+                        span: Some(span),
+                    };
+                    if tail {
+                        *caller = callee;
+                    } else {
+                        self.frames.push(callee);
                     }
-                    a => {
-                        let v = env
-                            .get(a)
-                            .ok_or_else(|| EvalError::Unbound(a.to_string()))
-                            .with_span(span.clone())?
-                            .clone();
-
-                        apply_value(v, &mut env, stack).with_span(span.clone())?;
+                    return Ok(());
+                }
+                Value::BuiltIn(name, f) => {
+                    let (name, f) = (*name, **f);
+                    // The name pop or push is about to take off the stack
+                    let atom = match name {
+                        POP | PUSH => self.stack.last().and_then(|v| v.get_name().ok()),
+                        _ => None,
                     }
-                },
-                Expr::Thunk(e, _) => {
-                    let t = Value::Thunk {
-                        env: env.clone(),
-                        exprs: e.to_vec(),
-                    };
+                    .map(str::to_string);
 
-                    stack.push(t);
+                    let env = &mut self.frames[top].env;
+                    f(env, &mut self.stack).with_span(span)?;
+
+                    match (name, atom) {
+                        (POP, Some(a)) => env.get(&a).inspect(|v| self.observer.bind(&a, v)),
+                        (PUSH, Some(a)) => {
+                            self.stack.last().inspect(|v| self.observer.lookup(&a, v))
+                        }
+                        _ => None,
+                    };
+                    return Ok(());
+                }
+                v => {
+                    return Err(EvalError::InvalidApply(v.type_name().to_string())).with_span(span)
                 }
             }
         }
-        println!("RETURN");
+    }
 
+    /// Collects the elements of a finished list or map literal.
+    fn finish(&mut self, frame: Frame) -> Result<(), EvalStacktrace> {
+        let items = |stack: &mut Vec<Value>, mark: usize| stack.split_off(mark.min(stack.len()));
+        match frame.kind {
+            FrameKind::Thunk => {}
+            FrameKind::List(mark) => {
+                let items = items(&mut self.stack, mark);
+                self.stack.push(Value::List(items.into_iter().collect()));
+            }
+            FrameKind::Map(mark) => {
+                let items = items(&mut self.stack, mark);
+                let map = Value::from_map_items(items).map_err(|e| {
+                    let mut e = EvalStacktrace::from(e);
+                    e.stack.extend(frame.span);
+                    e
+                })?;
+                self.stack.push(map);
+            }
+        }
         Ok(())
     }
 }
@@ -242,26 +932,126 @@ impl EvalCtx<'_, '_> {
 mod builtin {
     use super::*;
 
-    pub fn inc(_e: &mut Env, s: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
-        let i = s.pop().ok_or(EvalError::PopEmpty)?.get_integer()?;
+    pub fn inc(e: &mut Env, s: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        s.push(Value::Integer(1));
+
+        add(e, s)
+    }
+
+    /// Pops b then a and pushes `a op b`. If either is a float, both are
+    /// converted to floats. Otherwise this tries i64 first and redoes the
+    /// operation on big integers if that overflows. `big` returns None when
+    /// dividing by zero.
+    fn numeric_op(
+        stack: &mut Vec<Value>,
+        small: fn(i64, i64) -> Option<i64>,
+        big: fn(&BigInt, &BigInt) -> Option<BigInt>,
+        float: fn(f64, f64) -> f64,
+    ) -> Result<(), EvalStacktrace> {
+        let b = stack.pop().ok_or(EvalError::PopEmpty)?;
+        let a = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let r = match (&a, &b) {
+            (Value::Integer(x), Value::Integer(y)) => small(*x, *y).map(Value::Integer),
+            _ => None,
+        };
+
+        let r = match r {
+            Some(r) => r,
+            None if matches!(a, Value::Float(_)) || matches!(b, Value::Float(_)) => {
+                Value::Float(float(a.get_float()?, b.get_float()?))
+            }
+            None => {
+                let r = big(&a.get_bigint()?, &b.get_bigint()?).ok_or(EvalError::DivideByZero)?;
+                Value::from_bigint(r)
+            }
+        };
+
+        stack.push(r);
+
+        Ok(())
+    }
+
+    pub fn add(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        numeric_op(stack, i64::checked_add, |a, b| Some(a + b), |a, b| a + b)
+    }
+
+    pub fn sub(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        numeric_op(stack, i64::checked_sub, |a, b| Some(a - b), |a, b| a - b)
+    }
+
+    pub fn mul(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        numeric_op(stack, i64::checked_mul, |a, b| Some(a * b), |a, b| a * b)
+    }
+
+    pub fn div(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        numeric_op(
+            stack,
+            i64::checked_div,
+            |a, b| a.div_rem(b).map(|(q, _)| q),
+            |a, b| a / b,
+        )
+    }
+
+    pub fn rem(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        numeric_op(
+            stack,
+            i64::checked_rem,
+            |a, b| a.div_rem(b).map(|(_, r)| r),
+            |a, b| a % b,
+        )
+    }
+
+    pub fn float(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let x = stack.pop().ok_or(EvalError::PopEmpty)?.get_float()?;
+
+        stack.push(Value::Float(x));
 
-        s.push(Value::Integer(i + 1));
+        Ok(())
+    }
+
+    /// Pops a number and pushes it rounded to an integer, which leaves
+    /// integers as they are.
+    fn to_integer(stack: &mut Vec<Value>, round: fn(f64) -> f64) -> Result<(), EvalStacktrace> {
+        let v = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let r = match v {
+            Value::Float(x) => {
+                Value::from_bigint(BigInt::from_f64(round(x)).ok_or(EvalError::NotFinite(x))?)
+            }
+            v => Value::from_bigint(v.get_bigint()?),
+        };
+
+        stack.push(r);
 
         Ok(())
     }
 
+    pub fn truncate(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        to_integer(stack, f64::trunc)
+    }
+
+    pub fn floor(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        to_integer(stack, f64::floor)
+    }
+
+    pub fn ceil(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        to_integer(stack, f64::ceil)
+    }
+
+    pub fn round(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        to_integer(stack, f64::round)
+    }
+
     pub fn pop(env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
         let name = stack
             .pop()
             .ok_or(EvalError::PopEmpty)?
-            .get_name()
-            .unwrap() // TODO
+            .get_name()?
             .to_string();
 
         let value = stack.pop().ok_or(EvalError::PopEmpty)?;
 
-        println!("POP {name} = {value}");
-
         env.insert_mut(name, value);
 
         Ok(())
@@ -271,31 +1061,33 @@ mod builtin {
         let name = stack
             .pop()
             .ok_or(EvalError::PopEmpty)?
-            .get_name()
-            .unwrap() // TODO
+            .get_name()?
             .to_string();
 
         let value = env
             .get(&name)
             .ok_or_else(|| EvalError::Unbound(name.to_string()))?;
 
-        println!("PUSH {name}, {value}");
-
         stack.push(value.clone());
 
         Ok(())
     }
 
-    pub fn force(env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
-        let value = stack.pop().ok_or(EvalError::PopEmpty)?;
-
-        println!("FORCE {}", value);
-
-        apply_value(value, env, stack)
+    /// Never called, since `EvalCtx::apply` forces values itself. It only
+    /// gives `force` a value, so it can be bound and passed around.
+    pub fn force(_env: &mut Env, _stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        Ok(())
     }
 
     pub fn cswap(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
-        let value = stack.pop().ok_or(EvalError::PopEmpty)?;
+        if stack.len() < 3 {
+            return Err(EvalError::StackUnderflow {
+                needed: 3,
+                had: stack.len(),
+            }
+            .into());
+        }
+        let value = stack.pop().unwrap();
 
         if value == Value::Atom("t".to_string()) {
             let i_last = stack.len() - 1;
@@ -306,36 +1098,314 @@ mod builtin {
         Ok(())
     }
 
-    pub fn println(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
-        let value = stack.pop().ok_or(EvalError::PopEmpty)?;
+    pub fn thunk(env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let q = stack.pop().ok_or(EvalError::PopEmpty)?;
 
-        println!("{value}");
+        stack.push(Value::Thunk {
+            env: env.clone(),
+            exprs: q.get_quotation()?.to_vec(),
+        });
 
         Ok(())
     }
 
-    // pub fn eq(env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
-    //     let value = stack.pop().ok_or(EvalError::PopEmpty)?;
+    /// Like `thunk`, but closes over the env of another thunk.
+    pub fn thunk_in(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        match &mut stack.pop().ok_or(EvalError::PopEmpty)? {
+            Value::Thunk { env, .. } => thunk(env, stack),
+            v => Err(EvalError::TypeMismatch(
+                "thunk".to_string(),
+                v.type_name().to_string(),
+            ))
+            .to_stacktrace(),
+        }
+    }
 
-    //     println!("{}", value);
+    pub fn length(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let v = stack.pop().ok_or(EvalError::PopEmpty)?;
 
-    //     apply_value(value, env, stack)
-    // }
+        let len = match &v {
+            Value::List(items) => items.len(),
+            Value::Map(map) => map.size(),
+            Value::Bytes(b) => b.len(),
+            q => q.get_quotation()?.len(),
+        };
 
-    #[cfg(test)]
-    mod builtin_tests {
-        use super::*;
+        stack.push(Value::Integer(len as i64));
 
-        // #[test]
-        // fn test_inc() {
-        //     let mut s = vec![Value::Integer(0)];
-        //     let mut e = Env::new();
-        //     assert_eq!(inc(&mut e, &mut s), Ok(()));
-        //     assert_eq!(s, vec![Value::Integer(1)]);
+        Ok(())
+    }
 
-        //     let mut s = vec![];
-        //     let mut e = Env::new();
-        //     assert_eq!(inc(&mut e, &mut s), Err(EvalError::));
+    /// Works on lists, quotations and bytes.
+    pub fn nth(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let (index, i) = stack.pop().ok_or(EvalError::PopEmpty)?.get_index()?;
+
+        let v = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let (v, len) = match &v {
+            Value::List(items) => (i.and_then(|i| items.get(i)).cloned(), items.len()),
+            Value::Bytes(b) => (
+                i.and_then(|i| b.get(i)).map(|b| Value::Integer(*b as i64)),
+                b.len(),
+            ),
+            q => {
+                let exprs = q.get_quotation()?;
+                let v = i
+                    .and_then(|i| exprs.get(i))
+                    .map(Value::from_quoted_expr)
+                    .transpose()?;
+                (v, exprs.len())
+            }
+        };
+
+        stack.push(v.ok_or(EvalError::IndexOutOfBounds { index, len })?);
+
+        Ok(())
+    }
+
+    /// The elements from start up to but not including end of a list or
+    /// bytes.
+    pub fn slice(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let (end, e) = stack.pop().ok_or(EvalError::PopEmpty)?.get_index()?;
+        let (start, s) = stack.pop().ok_or(EvalError::PopEmpty)?.get_index()?;
+        let v = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let len = match &v {
+            Value::Bytes(b) => b.len(),
+            v => v.get_list()?.len(),
+        };
+        let (start, end) = match (s, e) {
+            (Some(s), Some(e)) if s <= e && e <= len => (s, e),
+            _ => return Err(EvalError::InvalidRange { start, end, len }).to_stacktrace(),
+        };
+
+        stack.push(match &v {
+            Value::Bytes(b) => Value::Bytes(b[start..end].to_vec()),
+            v => Value::List(
+                v.get_list()?
+                    .iter()
+                    .skip(start)
+                    .take(end - start)
+                    .cloned()
+                    .collect(),
+            ),
+        });
+
+        Ok(())
+    }
+
+    pub fn char_to_integer(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        match stack.pop().ok_or(EvalError::PopEmpty)? {
+            Value::Char(c) => stack.push(Value::Integer(c as i64)),
+            v => {
+                return Err(EvalError::TypeMismatch(
+                    "char".to_string(),
+                    v.type_name().to_string(),
+                ))
+                .to_stacktrace()
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn integer_to_char(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let v = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let c = v
+            .get_bigint()?
+            .to_i64()
+            .and_then(|i| u32::try_from(i).ok())
+            .and_then(char::from_u32)
+            .ok_or_else(|| EvalError::InvalidChar(v.to_string()))?;
+
+        stack.push(Value::Char(c));
+
+        Ok(())
+    }
+
+    /// The bytes as a list of integers.
+    pub fn bytes_to_list(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        match &stack.pop().ok_or(EvalError::PopEmpty)? {
+            Value::Bytes(b) => stack.push(Value::List(
+                b.iter().map(|b| Value::Integer(*b as i64)).collect(),
+            )),
+            v => {
+                return Err(EvalError::TypeMismatch(
+                    "bytes".to_string(),
+                    v.type_name().to_string(),
+                ))
+                .to_stacktrace()
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A list of integers from 0 to 255 as bytes.
+    pub fn list_to_bytes(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let l = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let b = l
+            .get_list()?
+            .iter()
+            .map(|v| match v {
+                Value::Integer(i) => {
+                    u8::try_from(*i).map_err(|_| EvalError::NotAByte(v.to_string()))
+                }
+                v => Err(EvalError::NotAByte(v.to_string())),
+            })
+            .collect::<Result<_, _>>()?;
+
+        stack.push(Value::Bytes(b));
+
+        Ok(())
+    }
+
+    pub fn append(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let v = stack.pop().ok_or(EvalError::PopEmpty)?;
+        let l = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        stack.push(Value::List(l.get_list()?.push_back(v)));
+
+        Ok(())
+    }
+
+    pub fn concat(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let b = stack.pop().ok_or(EvalError::PopEmpty)?;
+        let a = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let mut items = a.get_list()?.clone();
+        for v in b.get_list()?.iter() {
+            items.push_back_mut(v.clone());
+        }
+
+        stack.push(Value::List(items));
+
+        Ok(())
+    }
+
+    /// Pushes 't if the list is empty and 'f otherwise, for use with
+    /// `cswap`.
+    pub fn is_empty(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let l = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let b = if l.get_list()?.is_empty() { "t" } else { "f" };
+        stack.push(Value::Atom(b.to_string()));
+
+        Ok(())
+    }
+
+    /// Splits a list into the rest of the list and its first element, which
+    /// ends up on top.
+    pub fn uncons(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let l = stack.pop().ok_or(EvalError::PopEmpty)?;
+        let items = l.get_list()?;
+
+        let first = items.first().ok_or(EvalError::IndexOutOfBounds {
+            index: BigInt::from_i64(0),
+            len: 0,
+        })?;
+
+        stack.push(Value::List(items.iter().skip(1).cloned().collect()));
+        stack.push(first.clone());
+
+        Ok(())
+    }
+
+    pub fn get(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let k = Key::from_value(&stack.pop().ok_or(EvalError::PopEmpty)?)?;
+        let m = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let v = m
+            .get_map()?
+            .get(&k)
+            .ok_or_else(|| EvalError::KeyNotFound(k.to_string()))?;
+
+        stack.push(v.clone());
+
+        Ok(())
+    }
+
+    pub fn assoc(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let v = stack.pop().ok_or(EvalError::PopEmpty)?;
+        let k = Key::from_value(&stack.pop().ok_or(EvalError::PopEmpty)?)?;
+        let m = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        stack.push(Value::Map(m.get_map()?.insert(k, v)));
+
+        Ok(())
+    }
+
+    /// Removing a key that isn't there is fine.
+    pub fn dissoc(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let k = Key::from_value(&stack.pop().ok_or(EvalError::PopEmpty)?)?;
+        let m = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        stack.push(Value::Map(m.get_map()?.remove(&k)));
+
+        Ok(())
+    }
+
+    /// The keys of a map as a list, in order.
+    pub fn keys(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let m = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let keys = sorted_entries(m.get_map()?)
+            .into_iter()
+            .map(|(k, _)| k.to_value())
+            .collect();
+
+        stack.push(Value::List(keys));
+
+        Ok(())
+    }
+
+    /// Pushes 't if the map has the key and 'f otherwise.
+    pub fn contains(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let k = Key::from_value(&stack.pop().ok_or(EvalError::PopEmpty)?)?;
+        let m = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        let b = if m.get_map()?.contains_key(&k) {
+            "t"
+        } else {
+            "f"
+        };
+        stack.push(Value::Atom(b.to_string()));
+
+        Ok(())
+    }
+
+    pub fn println(_env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+        let value = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+        println!("{value}");
+
+        Ok(())
+    }
+
+    // pub fn eq(env: &mut Env, stack: &mut Vec<Value>) -> Result<(), EvalStacktrace> {
+    //     let value = stack.pop().ok_or(EvalError::PopEmpty)?;
+
+    //     println!("{}", value);
+
+    //     apply_value(value, env, stack)
+    // }
+
+    #[cfg(test)]
+    mod builtin_tests {
+        use super::*;
+
+        // #[test]
+        // fn test_inc() {
+        //     let mut s = vec![Value::Integer(0)];
+        //     let mut e = Env::new();
+        //     assert_eq!(inc(&mut e, &mut s), Ok(()));
+        //     assert_eq!(s, vec![Value::Integer(1)]);
+
+        //     let mut s = vec![];
+        //     let mut e = Env::new();
+        //     assert_eq!(inc(&mut e, &mut s), Err(EvalError::));
         //     assert_eq!(s, vec![Value::Integer(1)]);
         // }
 
@@ -351,6 +1421,19 @@ mod builtin {
             cswap(&mut Env::new(), &mut stack).unwrap();
 
             assert_eq!(stack, vec![Integer(2), Integer(1)]);
+
+            let mut stack = vec![Integer(1), Atom("t".to_string())];
+            assert_eq!(
+                cswap(&mut Env::new(), &mut stack).unwrap_err().error,
+                EvalError::StackUnderflow { needed: 3, had: 2 }
+            );
+            // Checked before anything is taken off
+            assert_eq!(stack.len(), 2);
+
+            assert_eq!(
+                cswap(&mut Env::new(), &mut vec![]).unwrap_err().error,
+                EvalError::StackUnderflow { needed: 3, had: 0 }
+            );
         }
     }
 }
@@ -363,32 +1446,73 @@ fn env_with_builtins() -> Env {
     };
 
     insert("inc", builtin::inc);
-    insert("pop", builtin::pop);
-    insert("push", builtin::push);
-    insert("force", builtin::force);
+    insert("+", builtin::add);
+    insert("-", builtin::sub);
+    insert("*", builtin::mul);
+    insert("/", builtin::div);
+    insert("%", builtin::rem);
+    insert("float", builtin::float);
+    insert("truncate", builtin::truncate);
+    insert("floor", builtin::floor);
+    insert("ceil", builtin::ceil);
+    insert("round", builtin::round);
+    insert(POP, builtin::pop);
+    insert(PUSH, builtin::push);
+    insert(FORCE, builtin::force);
     insert("cswap", builtin::cswap);
     insert("println", builtin::println);
+    insert("thunk", builtin::thunk);
+    insert("thunk-in", builtin::thunk_in);
+    insert("length", builtin::length);
+    insert("nth", builtin::nth);
+    insert("append", builtin::append);
+    insert("concat", builtin::concat);
+    insert("empty?", builtin::is_empty);
+    insert("uncons", builtin::uncons);
+    insert("slice", builtin::slice);
+    insert("char->integer", builtin::char_to_integer);
+    insert("integer->char", builtin::integer_to_char);
+    insert("bytes->list", builtin::bytes_to_list);
+    insert("list->bytes", builtin::list_to_bytes);
+    insert("get", builtin::get);
+    insert("assoc", builtin::assoc);
+    insert("dissoc", builtin::dissoc);
+    insert("keys", builtin::keys);
+    insert("contains?", builtin::contains);
 
     env
 }
 
 pub fn eval(exprs: &[Expr]) -> Result<Vec<Value>, EvalStacktrace> {
-    let mut stack = Vec::new();
-    let ec = EvalCtx {
+    eval_with_limits(exprs, &EvalLimits::default())
+}
+
+pub fn eval_with_limits(exprs: &[Expr], limits: &EvalLimits) -> Result<Vec<Value>, EvalStacktrace> {
+    eval_with(
         exprs,
-        env: env_with_builtins(),
-        stack: &mut stack,
-        tracing: true,
-    };
+        EvalOptions {
+            limits: limits.clone(),
+            ..Default::default()
+        },
+        &mut Silent,
+    )
+}
+
+pub fn eval_with(
+    exprs: &[Expr],
+    opts: EvalOptions,
+    observer: &mut dyn Observer,
+) -> Result<Vec<Value>, EvalStacktrace> {
+    let mut ctx = EvalCtx::new(exprs.to_vec(), env_with_builtins(), opts, observer);
 
-    ec.eval()?;
+    ctx.run()?;
 
-    Ok(stack)
+    Ok(ctx.stack)
 }
 
 #[cfg(test)]
 mod eval_test {
-    use crate::parser::parser;
+    use crate::parser::{file_parser, parser, FileId};
     use chumsky::Parser;
 
     use super::*;
@@ -420,4 +1544,633 @@ mod eval_test {
 
         assert_eq!(eval(&e).unwrap(), vec![Value::Integer(1)]);
     }
+
+    #[test]
+    fn test_arithmetic() {
+        let run = |src: &str| eval(&parser().parse(src).unwrap());
+
+        assert_eq!(
+            run("7 2 - 7 2 * 7 2 / 7 2 % -7 2 /").unwrap(),
+            vec![
+                Value::Integer(5),
+                Value::Integer(14),
+                Value::Integer(3),
+                Value::Integer(1),
+                Value::Integer(-3)
+            ]
+        );
+
+        let big = run("9223372036854775807 inc").unwrap();
+        assert!(matches!(big[0], Value::BigInt(_)));
+        assert_eq!(big[0].to_string(), "9223372036854775808");
+
+        assert_eq!(
+            run("9223372036854775807 inc -1 +").unwrap(),
+            vec![Value::Integer(i64::MAX)]
+        );
+        assert_eq!(
+            run("-9223372036854775808 -1 *").unwrap()[0].to_string(),
+            "9223372036854775808"
+        );
+        assert_eq!(
+            run("4294967296 4294967296 * 4294967296 * 3 /").unwrap()[0].to_string(),
+            "26409387504754779197847983445"
+        );
+
+        assert_eq!(run("1 0 /").unwrap_err().error, EvalError::DivideByZero);
+        assert_eq!(
+            run("1 'a +").unwrap_err().error,
+            EvalError::TypeMismatch("integer".to_string(), "atom".to_string())
+        );
+    }
+
+    #[test]
+    fn test_float() {
+        let run = |src: &str| eval(&parser().parse(src).unwrap());
+
+        assert_eq!(
+            run("1.5 2 * 1 2.5 - 7 2.0 / 1 float 5.5 2 %").unwrap(),
+            vec![
+                Value::Float(3.0),
+                Value::Float(-1.5),
+                Value::Float(3.5),
+                Value::Float(1.0),
+                Value::Float(1.5)
+            ]
+        );
+        assert_eq!(
+            run("9223372036854775807 inc 0.5 +").unwrap(),
+            vec![Value::Float(9223372036854775808.5)]
+        );
+        assert_eq!(run("1.0 0 /").unwrap(), vec![Value::Float(f64::INFINITY)]);
+
+        assert_eq!(
+            run("-2.5 truncate -2.5 floor -2.5 ceil -2.5 round 7 round").unwrap(),
+            vec![
+                Value::Integer(-2),
+                Value::Integer(-3),
+                Value::Integer(-2),
+                Value::Integer(-3),
+                Value::Integer(7)
+            ]
+        );
+        assert_eq!(
+            run("1e20 truncate").unwrap()[0].to_string(),
+            "100000000000000000000"
+        );
+        assert!(matches!(
+            run("#nan round").unwrap_err().error,
+            EvalError::NotFinite(x) if x.is_nan()
+        ));
+
+        let s = run("1.0 0.1 1e100 -0.0 #-inf").unwrap();
+        let shown: Vec<String> = s.iter().map(|v| v.to_string()).collect();
+        assert_eq!(shown, vec!["1.0", "0.1", "1e100", "-0.0", "#-inf"]);
+        assert_eq!(run(&shown.join(" ")).unwrap(), s);
+    }
+
+    #[test]
+    fn test_quotation() {
+        let run = |src: &str| eval(&parser().parse(src).unwrap());
+
+        let q = run("'(1 $x (^x) 2.5)").unwrap();
+        assert_eq!(q[0].to_string(), "'( 1 quote x pop ( quote x push ) 2.5 )");
+
+        assert_eq!(
+            run("'(1 'a (b) \"c\") $q ^q length ^q 1 nth ^q 3 nth ^q 4 nth").unwrap(),
+            vec![
+                Value::Integer(5),
+                Value::Atom("quote".to_string()),
+                Value::Quotation(vec![Expr::Atom(
+                    "b".to_string(),
+                    Span::new(FileId::default(), 8..9)
+                )]),
+                Value::String("c".to_string()),
+            ]
+        );
+        assert_eq!(
+            run("'(1) 1 nth").unwrap_err().error,
+            EvalError::IndexOutOfBounds {
+                index: BigInt::from_i64(1),
+                len: 1
+            }
+        );
+
+        // thunk closes over the env where it runs
+        assert_eq!(
+            run("1 $x '(^x inc) $q 10 $x ^q thunk force").unwrap(),
+            vec![Value::Integer(11)]
+        );
+        // thunk-in closes over the env of another thunk
+        assert_eq!(
+            run("1 $x (^x) $t 10 $x '(^x inc) ^t thunk-in force").unwrap(),
+            vec![Value::Integer(2)]
+        );
+
+        assert_eq!(
+            run("'(1) force").unwrap_err().error,
+            EvalError::InvalidApply("quotation".to_string())
+        );
+    }
+
+    #[test]
+    fn test_list() {
+        let run = |src: &str| eval(&parser().parse(src).unwrap());
+        let list = |vs: Vec<Value>| Value::List(vs.into_iter().collect());
+
+        assert_eq!(
+            run("1 $x [^x 2 inc (3)] $l ^l length ^l 0 nth ^l 1 nth ^x").unwrap(),
+            vec![
+                Value::Integer(3),
+                Value::Integer(1),
+                Value::Integer(3),
+                Value::Integer(1),
+            ]
+        );
+        assert_eq!(run("[[] []]").unwrap()[0].to_string(), "[ [ ] [ ] ]");
+
+        // Bindings inside a list don't escape it
+        assert_eq!(
+            run("1 $x [2 $x ^x] ^x").unwrap(),
+            vec![list(vec![Value::Integer(2)]), Value::Integer(1)]
+        );
+
+        assert_eq!(
+            run("[1] 2 append [3 4] concat").unwrap(),
+            vec![list(vec![
+                Value::Integer(1),
+                Value::Integer(2),
+                Value::Integer(3),
+                Value::Integer(4),
+            ])]
+        );
+        assert_eq!(
+            run("[1 2] uncons [] empty? [1] empty?").unwrap(),
+            vec![
+                list(vec![Value::Integer(2)]),
+                Value::Integer(1),
+                Value::Atom("t".to_string()),
+                Value::Atom("f".to_string()),
+            ]
+        );
+
+        // Sum a list by looping until it's empty
+        assert_eq!(
+            run(r"(
+  ($loop $acc $l
+    (^acc)
+    (^l uncons ^acc + ^loop ^loop force)
+    ^l empty? cswap $next $_ ^next force) $sum
+  [1 2 3 4] 0 ^sum ^sum force
+) force")
+            .unwrap(),
+            vec![Value::Integer(10)]
+        );
+
+        assert_eq!(
+            run("'[a (b)]").unwrap(),
+            vec![list(vec![
+                Value::Atom("a".to_string()),
+                Value::Quotation(vec![Expr::Atom(
+                    "b".to_string(),
+                    Span::new(FileId::default(), 5..6)
+                )]),
+            ])]
+        );
+        assert_eq!(
+            run("[1] 1 nth").unwrap_err().error,
+            EvalError::IndexOutOfBounds {
+                index: BigInt::from_i64(1),
+                len: 1
+            }
+        );
+        assert_eq!(
+            run("[] uncons").unwrap_err().error,
+            EvalError::IndexOutOfBounds {
+                index: BigInt::from_i64(0),
+                len: 0
+            }
+        );
+        assert_eq!(
+            run("1 2 append").unwrap_err().error,
+            EvalError::TypeMismatch("list".to_string(), "integer".to_string())
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        let run = |src: &str| eval(&parser().parse(src).unwrap());
+
+        let m = run(r#"{'b 2 1 "one" "c" [3] 'b 4}"#).unwrap();
+        assert_eq!(m[0].to_string(), "{ 1 one b 4 c [ 3 ] }");
+
+        assert_eq!(
+            run(r#"{'a 1 "a" 2} $m ^m 'a get ^m "a" get ^m length"#).unwrap(),
+            vec![Value::Integer(1), Value::Integer(2), Value::Integer(2)]
+        );
+        // Big and small integers are the same keys
+        assert_eq!(
+            run("{9223372036854775807 inc 'big} 9223372036854775807 inc get").unwrap(),
+            vec![Value::Atom("big".to_string())]
+        );
+
+        assert_eq!(
+            run("{} 'a 1 assoc 'b 2 assoc 'a dissoc 'c dissoc $m ^m keys ^m 'b contains? ^m 'a contains?")
+                .unwrap(),
+            vec![
+                Value::List(vec![Value::Atom("b".to_string())].into_iter().collect()),
+                Value::Atom("t".to_string()),
+                Value::Atom("f".to_string()),
+            ]
+        );
+        // Updates don't change the original
+        assert_eq!(
+            run("{'a 1} $m ^m 'a 2 assoc $_ ^m 'a get").unwrap(),
+            vec![Value::Integer(1)]
+        );
+
+        assert_eq!(
+            run("'{a (b) c 1}").unwrap()[0].to_string(),
+            "{ a '( b ) c 1 }"
+        );
+        assert_eq!(
+            run("{} 'a get").unwrap_err().error,
+            EvalError::KeyNotFound("a".to_string())
+        );
+        assert_eq!(
+            run("{'a}").unwrap_err().error,
+            EvalError::MissingMapValue("a".to_string())
+        );
+        assert_eq!(
+            run("{1.5 1}").unwrap_err().error,
+            EvalError::InvalidKey("float".to_string())
+        );
+        assert_eq!(
+            run("'{(a) 1}").unwrap_err().error,
+            EvalError::InvalidKey("quotation".to_string())
+        );
+    }
+
+    #[test]
+    fn test_char_and_bytes() {
+        let run = |src: &str| eval(&parser().parse(src).unwrap());
+
+        assert_eq!(
+            run(r"#\a char->integer 955 integer->char").unwrap(),
+            vec![Value::Integer(97), Value::Char('λ')]
+        );
+        assert_eq!(
+            run(r#"b"\x00ab\xff" $b ^b length ^b 3 nth ^b 1 3 slice"#).unwrap(),
+            vec![
+                Value::Integer(4),
+                Value::Integer(255),
+                Value::Bytes(b"ab".to_vec()),
+            ]
+        );
+        assert_eq!(
+            run(r#"b"hi" bytes->list $l ^l ^l list->bytes"#).unwrap(),
+            vec![
+                Value::List(
+                    vec![Value::Integer(104), Value::Integer(105)]
+                        .into_iter()
+                        .collect()
+                ),
+                Value::Bytes(b"hi".to_vec()),
+            ]
+        );
+        assert_eq!(
+            run("[1 2 3] 1 3 slice [1] 1 1 slice").unwrap(),
+            vec![
+                Value::List(
+                    vec![Value::Integer(2), Value::Integer(3)]
+                        .into_iter()
+                        .collect()
+                ),
+                Value::List(Vector::new()),
+            ]
+        );
+        assert_eq!(
+            run(r#"b"a\n\x7f" "#).unwrap()[0].to_string(),
+            r#"b"a\n\x7f""#
+        );
+
+        assert_eq!(
+            run(r#"b"ab" 1 3 slice"#).unwrap_err().error,
+            EvalError::InvalidRange {
+                start: BigInt::from_i64(1),
+                end: BigInt::from_i64(3),
+                len: 2
+            }
+        );
+        assert_eq!(
+            run(r#"b"ab" 2 1 slice"#).unwrap_err().error,
+            EvalError::InvalidRange {
+                start: BigInt::from_i64(2),
+                end: BigInt::from_i64(1),
+                len: 2
+            }
+        );
+        // Errors show the index as given, even if it can't be one
+        assert_eq!(
+            run("[1] -1 nth").unwrap_err().error.to_string(),
+            "Index -1 out of bounds for length 1"
+        );
+        assert_eq!(
+            run("[1] 9223372036854775807 1 + nth")
+                .unwrap_err()
+                .error
+                .to_string(),
+            "Index 9223372036854775808 out of bounds for length 1"
+        );
+        assert_eq!(
+            run(r#"b"ab" -1 1 slice"#).unwrap_err().error.to_string(),
+            "Range -1..1 out of bounds for length 2"
+        );
+        assert_eq!(
+            run("1114112 integer->char").unwrap_err().error,
+            EvalError::InvalidChar("1114112".to_string())
+        );
+        assert_eq!(
+            run("[1 256] list->bytes").unwrap_err().error,
+            EvalError::NotAByte("256".to_string())
+        );
+        assert_eq!(
+            run(r"#\a force").unwrap_err().error,
+            EvalError::InvalidApply("char".to_string())
+        );
+    }
+
+    #[test]
+    fn test_pragma() {
+        let run = |src: &str| eval(&parser().parse(src).unwrap());
+
+        assert_eq!(
+            run("#!/usr/bin/env frospy\n#frospy 1\n1 inc").unwrap(),
+            vec![Value::Integer(2)]
+        );
+        let err = run("#frospy 99\n1 inc").unwrap_err();
+        assert_eq!(
+            err.error,
+            EvalError::Pragma(PragmaError::UnsupportedVersion(99))
+        );
+        assert_eq!(
+            err.stack.first(),
+            Some(&Span::new(FileId::default(), 0..10))
+        );
+        assert_eq!(
+            run("#frospy 1 macros").unwrap_err().error,
+            EvalError::Pragma(PragmaError::UnknownExtension("macros".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_stacktrace() {
+        // Not a tail call, since it adds the head afterwards
+        let src = "($self $l\n  ^l uncons $h\n  ^self ^self force ^h +) $walk\n\
+                   [1 2 3] ^walk ^walk force";
+        let mut sources = SourceMap::new();
+        let file = sources.add("walk.fpy", src);
+        let err = eval(&file_parser(file).parse(src).unwrap()).unwrap_err();
+
+        assert_eq!(err.stack.len(), 5);
+        assert_eq!(
+            err.render(&sources),
+            "error[F0208]: Index 0 out of bounds for length 0\n   \
+             0: walk.fpy:2:6         uncons\n   \
+             1: walk.fpy:3:15        force\n      \
+             frame repeated 3 times\n   \
+             4: walk.fpy:4:21        force\n"
+        );
+
+        // Tail calls replace their caller's frame, so only the last is left
+        let src = "($self $l\n  ^l uncons $h\n  ^self ^self force) $walk\n\
+                   [1 2 3] ^walk ^walk force";
+        let file = sources.add("tail.fpy", src);
+        let err = eval(&file_parser(file).parse(src).unwrap()).unwrap_err();
+        assert_eq!(
+            err.render(&sources),
+            "error[F0208]: Index 0 out of bounds for length 0\n   \
+             0: tail.fpy:2:6         uncons\n   \
+             1: tail.fpy:3:15        force\n"
+        );
+
+        let a = Span::new(file, 0..1);
+        let b = Span::new(file, 1..2);
+        let c = Span::new(file, 2..3);
+        let stack = [&a, &b, &c, &b, &c, &b, &c, &a].map(Clone::clone);
+        assert_eq!(
+            fold_frames(&stack),
+            vec![(&stack[0..1], 1), (&stack[1..3], 3), (&stack[7..8], 1)]
+        );
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        let run = |src: &str| eval(&parser().parse(src).unwrap());
+        let n = 100_000;
+
+        // A loop in tail position doesn't grow the frame stack at all
+        let count = format!(
+            r"{{{n} 't}} $stop (
+  ($self $i
+    (^i)
+    (^i inc ^self ^self force)
+    ^stop ^i contains? cswap $next $_ ^next force) $count
+  0 ^count ^count force
+) force"
+        );
+        assert_eq!(run(&count).unwrap(), vec![Value::Integer(n)]);
+
+        // Nor does one that isn't, on the Rust stack
+        let depth = format!(
+            r"{{{n} 't}} $stop (
+  ($self $i
+    (0)
+    (^i inc ^self ^self force inc)
+    ^stop ^i contains? cswap $next $_ ^next force) $depth
+  0 ^depth ^depth force
+) force"
+        );
+        assert_eq!(run(&depth).unwrap(), vec![Value::Integer(n)]);
+    }
+
+    #[test]
+    fn test_limits() {
+        let run = |src: &str, limits: EvalLimits| {
+            eval_with_limits(&parser().parse(src).unwrap(), &limits).map_err(|e| e.error)
+        };
+
+        // Three expressions and the top level returning
+        let fuel = |n| EvalLimits {
+            fuel: Some(n),
+            ..Default::default()
+        };
+        assert_eq!(run("1 2 +", fuel(4)), Ok(vec![Value::Integer(3)]));
+        assert_eq!(run("1 2 +", fuel(3)), Err(EvalError::OutOfFuel(3)));
+
+        let limits = EvalLimits {
+            max_stack: Some(2),
+            max_frames: Some(3),
+            max_env: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            run("1 2 3", limits.clone()),
+            Err(EvalError::StackOverflow(2))
+        );
+        assert_eq!(
+            run("[[[1]]]", limits.clone()),
+            Err(EvalError::TooManyFrames(3))
+        );
+        assert_eq!(
+            run("[[1]] $l 2 $inc", limits.clone()).map(|s| s.len()),
+            Ok(0)
+        );
+        assert_eq!(run("1 $x 2 $y", limits), Err(EvalError::EnvTooLarge(1)));
+    }
+
+    /// Values nested as deeply as the reader allows print and drop
+    /// without recursing.
+    #[test]
+    fn test_deep_values() {
+        let n = 100_000;
+        for (open, close, shown_open) in [("[", "]", "[ "), ("(", ")", "( "), ("{0 ", "}", "{ 0 ")]
+        {
+            let src = format!("{}1{}", open.repeat(n), close.repeat(n));
+            let (exprs, diagnostics) = crate::parser::parse_file(FileId::default(), &src);
+            assert!(diagnostics.is_empty());
+
+            let stack = eval(&exprs).unwrap();
+            let shown = format!("{}1{}", shown_open.repeat(n), format!(" {close}").repeat(n));
+            assert!(stack[0].to_string() == shown, "{open}");
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        let src = "\n($f ^f ^f force) $f\n^f ^f force";
+        let file = FileId(3);
+        let exprs = file_parser(file).parse(src).unwrap();
+
+        // Says when the loop has gone round once, so it's cancelled mid-loop
+        struct Looped(Span, Option<std::sync::mpsc::Sender<()>>);
+        impl Observer for Looped {
+            fn force(&mut self, span: &Span, _tail: bool) {
+                if let Some(looped) = self.1.take_if(|_| *span == self.0) {
+                    looped.send(()).unwrap();
+                }
+            }
+        }
+
+        let inner = Span::new(file, 11..16);
+        let (looped, has_looped) = std::sync::mpsc::channel();
+        let mut observer = Looped(inner.clone(), Some(looped));
+        let opts = EvalOptions::default();
+        let cancel = opts.cancel.clone();
+        let run = std::thread::spawn(move || eval_with(&exprs, opts, &mut observer).map(|_| ()));
+        has_looped.recv().unwrap();
+        cancel.cancel();
+
+        let err = run.join().unwrap().unwrap_err();
+        assert_eq!(err.error, EvalError::Cancelled);
+        assert_eq!(err.error.code(), error_codes::CANCELLED);
+        assert!(err.stack.contains(&inner));
+    }
+
+    #[test]
+    fn test_string() {
+        let e = parser().parse(r#""hello\tworld" $s ^s ^s"#).unwrap();
+
+        let s = eval(&e).unwrap();
+        assert_eq!(
+            s,
+            vec![
+                Value::String("hello\tworld".to_string()),
+                Value::String("hello\tworld".to_string())
+            ]
+        );
+        assert_eq!(s[0].to_string(), "hello\tworld");
+
+        let e = parser().parse(r#""hello" force"#).unwrap();
+        assert_eq!(
+            eval(&e).unwrap_err().error,
+            EvalError::InvalidApply("string".to_string())
+        );
+    }
+
+    #[test]
+    fn test_never_panics() {
+        use rand::{rngs::StdRng, seq::IndexedRandom, Rng, SeedableRng};
+
+        let mut names: Vec<String> = env_with_builtins().keys().cloned().collect();
+        names.sort();
+        let mut tokens: Vec<&str> = names.iter().map(String::as_str).collect();
+        tokens.extend([
+            "0",
+            "1",
+            "-1",
+            "2",
+            "255",
+            "256",
+            "1114112",
+            "9223372036854775807",
+            "-9223372036854775808",
+            "0.5",
+            "-0.0",
+            "1e300",
+            "#nan",
+            "#inf",
+            "'t",
+            "'f",
+            "'a",
+            r#""s""#,
+            r"#\a",
+            r#"b"\xff""#,
+            "$a",
+            "^a",
+            "$b",
+            "^b",
+            "'",
+            "(",
+            ")",
+            "[",
+            "]",
+            "{",
+            "}",
+        ]);
+
+        // Loops run out of fuel rather than forever, and nothing is printed
+        let opts = || EvalOptions {
+            limits: EvalLimits {
+                fuel: Some(1_000),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let quiet: BuiltInFn = |_, stack| stack.pop().map(drop).ok_or(EvalError::PopEmpty.into());
+        let mut env = env_with_builtins();
+        env.insert_mut(
+            "println".to_string(),
+            Value::BuiltIn("println", Box::new(quiet)),
+        );
+
+        let parser = parser();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut ran = 0;
+        while ran < 2_000 {
+            let len = rng.random_range(1..12);
+            let src = (0..len)
+                .map(|_| *tokens.choose(&mut rng).unwrap())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let Ok(e) = parser.parse(src.as_str()) else {
+                continue;
+            };
+            ran += 1;
+            let r = std::panic::catch_unwind(|| {
+                EvalCtx::new(e.clone(), env.clone(), opts(), &mut Silent).run()
+            });
+            assert!(r.is_ok(), "eval panicked on {src:?}");
+        }
+    }
 }
@@ -0,0 +1,295 @@
+// Copyright (c) 2025 Azrea Amis
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Arbitrary precision integers that i64 arithmetic promotes to on overflow.
+//
+// This file is shared between the interpreter and compiled programs, which
+// get it pasted in verbatim, so it can't depend on anything outside std.
+
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// Sign and magnitude, with the magnitude as little-endian base 2^32 limbs.
+/// The magnitude never has trailing zero limbs, and zero is never negative.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    mag: Vec<u32>,
+}
+
+fn trim(mut mag: Vec<u32>) -> Vec<u32> {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    mag
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..a.len().max(b.len()) {
+        let s = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        out.push(s as u32);
+        carry = s >> 32;
+    }
+    out.push(carry as u32);
+    trim(out)
+}
+
+// Requires a >= b
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, x) in a.iter().enumerate() {
+        let mut d = *x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if d < 0 {
+            d += 1 << 32;
+            borrow = 1;
+        }
+        out.push(d as u32);
+    }
+    trim(out)
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = vec![0u32; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let t = *x as u64 * *y as u64 + out[i + j] as u64 + carry;
+            out[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        out[i + b.len()] = carry as u32;
+    }
+    trim(out)
+}
+
+fn div_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut out = vec![0u32; a.len()];
+    let mut rem = 0u64;
+    for i in (0..a.len()).rev() {
+        let cur = (rem << 32) | a[i] as u64;
+        out[i] = (cur / d as u64) as u32;
+        rem = cur % d as u64;
+    }
+    (trim(out), rem as u32)
+}
+
+// Binary long division, requires b to be non-zero
+fn div_rem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    let mut quot = vec![0u32; a.len()];
+    let mut rem: Vec<u32> = vec![];
+    for i in (0..a.len() * 32).rev() {
+        // rem = rem << 1 | bit i of a
+        let mut carry = (a[i / 32] >> (i % 32)) & 1;
+        for limb in rem.iter_mut() {
+            let next = *limb >> 31;
+            *limb = (*limb << 1) | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            rem.push(carry);
+        }
+        if cmp_mag(&rem, b) != Ordering::Less {
+            rem = sub_mag(&rem, b);
+            quot[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (trim(quot), rem)
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, mag: Vec<u32>) -> Self {
+        let mag = trim(mag);
+        BigInt {
+            negative: negative && !mag.is_empty(),
+            mag,
+        }
+    }
+
+    pub fn from_i64(i: i64) -> Self {
+        let u = i.unsigned_abs();
+        BigInt::from_parts(i < 0, vec![u as u32, (u >> 32) as u32])
+    }
+
+    /// The integer part of a float, or None if it is infinite or NaN.
+    pub fn from_f64(f: f64) -> Option<Self> {
+        if !f.is_finite() {
+            return None;
+        }
+        let f = f.trunc();
+        if f.abs() < 9.0e18 {
+            return Some(BigInt::from_i64(f as i64));
+        }
+
+        // Big enough that the exponent is positive, so this is
+        // mantissa * 2^exp with no fractional part
+        let bits = f.to_bits();
+        let exp = ((bits >> 52) & 0x7ff) as usize - 1075;
+        let mantissa = ((bits & ((1 << 52) - 1)) | (1 << 52)) as u128;
+
+        let mut mag = vec![0u32; exp / 32];
+        let shifted = mantissa << (exp % 32);
+        mag.extend((0..3).map(|i| (shifted >> (32 * i)) as u32));
+        Some(BigInt::from_parts(f < 0.0, mag))
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let u = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0u64, |acc, l| (acc << 32) | *l as u64);
+        if self.negative {
+            0i64.checked_sub_unsigned(u)
+        } else {
+            (u <= i64::MAX as u64).then_some(u as i64)
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let f = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0f64, |acc, l| acc * 4294967296.0 + *l as f64);
+        if self.negative {
+            -f
+        } else {
+            f
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    /// Truncating division and remainder, like i64's `/` and `%`. None when
+    /// dividing by zero.
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (q, r) = div_rem_mag(&self.mag, &other.mag);
+        Some((
+            BigInt::from_parts(self.negative != other.negative, q),
+            BigInt::from_parts(self.negative, r),
+        ))
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::from_parts(other.negative, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::from_parts(self.negative, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.mag.clone())
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &(-other)
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(
+            self.negative != other.negative,
+            mul_mag(&self.mag, &other.mag),
+        )
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return f.write_str("0");
+        }
+
+        // Peel off nine decimal digits at a time
+        let mut chunks = vec![];
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let (q, r) = div_small(&mag, 1_000_000_000);
+            chunks.push(r);
+            mag = q;
+        }
+
+        if self.negative {
+            f.write_str("-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        if let Some(c) = chunks.next() {
+            f.write_fmt(format_args!("{c}"))?;
+        }
+        for c in chunks {
+            f.write_fmt(format_args!("{c:09}"))?;
+        }
+        Ok(())
+    }
+}
@@ -18,20 +18,39 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::mem;
+use std::rc::Rc;
 
 // REMOVE
+use super::bigint::BigInt;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ThunkRef {}
+
+pub static FILES: &[&str] = &[];
+pub static SPANS: &[(u32, u32, u32)] = &[];
 // ENDREMOVE
 
+#[allow(unpredictable_function_pointer_comparisons)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Integer(i64),
+    BigInt(BigInt),
+    Float(f64),
     Atom(String),
+    String(String),
+    Char(char),
+    Bytes(Vec<u8>),
+    // Elements of a quoted thunk, along with the code to run it
+    Quotation { items: Rc<Vec<Value>>, fp: ThunkRef },
+    // Shared until modified, since there is no persistent vector here
+    List(Rc<Vec<Value>>),
+    Map(Rc<BTreeMap<MapKey, Value>>),
     Thunk { env: Env, fp: ThunkRef },
     BuiltIn(BuiltinFp),
 }
@@ -41,7 +60,51 @@ impl Display for Value {
         use Value::*;
         match self {
             Integer(i) => f.write_fmt(format_args!("{i}")),
+            BigInt(b) => f.write_fmt(format_args!("{b}")),
+            // Matches parser::float_literal
+            Float(x) if x.is_nan() => f.write_str("#nan"),
+            Float(x) if x.is_infinite() => f.write_str(if *x > 0.0 { "#inf" } else { "#-inf" }),
+            Float(x) => f.write_fmt(format_args!("{x:?}")),
             Atom(a) => f.write_fmt(format_args!("'{a}")),
+            String(s) => f.write_str(s),
+            Char(c) => f.write_fmt(format_args!("{c}")),
+            // Matches parser::bytes_literal
+            Bytes(b) => {
+                f.write_str("b\"")?;
+                for byte in b.iter() {
+                    match byte {
+                        b'"' => f.write_str("\\\"")?,
+                        b'\\' => f.write_str("\\\\")?,
+                        b'\n' => f.write_str("\\n")?,
+                        b'\r' => f.write_str("\\r")?,
+                        b'\t' => f.write_str("\\t")?,
+                        b' '..=b'~' => f.write_fmt(format_args!("{}", *byte as char))?,
+                        _ => f.write_fmt(format_args!("\\x{byte:02x}"))?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Quotation { items, .. } => {
+                f.write_str("'( ")?;
+                for v in items.iter() {
+                    f.write_fmt(format_args!("{v} "))?;
+                }
+                f.write_str(")")
+            }
+            List(items) => {
+                f.write_str("[ ")?;
+                for v in items.iter() {
+                    f.write_fmt(format_args!("{v} "))?;
+                }
+                f.write_str("]")
+            }
+            Map(map) => {
+                f.write_str("{ ")?;
+                for (k, v) in map.iter() {
+                    f.write_fmt(format_args!("{k} {v} "))?;
+                }
+                f.write_str("}")
+            }
             Thunk { fp, .. } => f.write_fmt(format_args!("&{fp:?}")),
             BuiltIn(fp) => f.write_fmt(format_args!("&{fp:?}")),
         }
@@ -52,7 +115,15 @@ impl Value {
     pub fn get_name(&self) -> Option<&str> {
         match self {
             Value::Integer(_) => None,
-            Value::Atom(s) => Some(&s),
+            Value::BigInt(_) => None,
+            Value::Float(_) => None,
+            Value::Atom(s) => Some(s),
+            Value::String(_) => None,
+            Value::Char(_) => None,
+            Value::Bytes(_) => None,
+            Value::Quotation { .. } => None,
+            Value::List(_) => None,
+            Value::Map(_) => None,
             Value::Thunk { .. } => None,
             Value::BuiltIn(_) => None,
         }
@@ -61,20 +132,162 @@ impl Value {
     pub fn get_integer(&self) -> Option<i64> {
         match self {
             Value::Integer(i) => Some(*i),
+            Value::BigInt(_) => None,
+            Value::Float(_) => None,
             Value::Atom(_) => None,
+            Value::String(_) => None,
+            Value::Char(_) => None,
+            Value::Bytes(_) => None,
+            Value::Quotation { .. } => None,
+            Value::List(_) => None,
+            Value::Map(_) => None,
             Value::Thunk { .. } => None,
             Value::BuiltIn(_) => None,
         }
     }
 
+    pub fn get_bigint(&self) -> Option<BigInt> {
+        match self {
+            Value::Integer(i) => Some(BigInt::from_i64(*i)),
+            Value::BigInt(b) => Some(b.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self) -> Option<f64> {
+        match self {
+            Value::Integer(i) => Some(*i as f64),
+            Value::BigInt(b) => Some(b.to_f64()),
+            Value::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// Integers are only big when they have to be.
+    pub fn from_bigint(b: BigInt) -> Value {
+        match b.to_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::BigInt(b),
+        }
+    }
+
     fn is_builtin(&self) -> bool {
+        matches!(self, Value::BuiltIn(_))
+    }
+
+    /// As the interpreter names it in errors
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) | Value::BigInt(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Atom(_) => "atom",
+            Value::String(_) => "string",
+            Value::Char(_) => "char",
+            Value::Bytes(_) => "bytes",
+            Value::Quotation { .. } => "quotation",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Thunk { .. } => "thunk",
+            Value::BuiltIn(_) => "builtin",
+        }
+    }
+}
+
+/// Codes of the interpreter's errors that can happen at runtime, as in
+/// `error_codes`.
+pub const UNBOUND: u16 = 201;
+pub const INVALID_APPLY: u16 = 202;
+pub const POP_EMPTY: u16 = 203;
+pub const TYPE_MISMATCH: u16 = 204;
+pub const DIVIDE_BY_ZERO: u16 = 206;
+pub const NOT_FINITE: u16 = 207;
+pub const INDEX_OUT_OF_BOUNDS: u16 = 208;
+pub const INVALID_KEY: u16 = 209;
+pub const KEY_NOT_FOUND: u16 = 210;
+pub const MISSING_MAP_VALUE: u16 = 211;
+pub const INVALID_RANGE: u16 = 212;
+pub const INVALID_CHAR: u16 = 213;
+pub const NOT_A_BYTE: u16 = 214;
+pub const NOT_A_NAME: u16 = 215;
+pub const STACK_UNDERFLOW: u16 = 216;
+
+/// One of the interpreter's errors, with the same code and message. The
+/// program stops by panicking with it, for `report_panic` to show.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub code: u16,
+    pub message: String,
+}
+
+pub fn fail(code: u16, message: impl Display) -> ! {
+    std::panic::panic_any(Error {
+        code,
+        message: message.to_string(),
+    })
+}
+
+fn type_mismatch(expected: &str, v: &Value) -> ! {
+    fail(
+        TYPE_MISMATCH,
+        format!("Type mismatch, expected {expected}, got {}", v.type_name()),
+    )
+}
+
+fn pop(stack: &mut Stack) -> Value {
+    stack
+        .pop()
+        .unwrap_or_else(|| fail(POP_EMPTY, "Attempted to pop from empty stack"))
+}
+
+fn pop_name(stack: &mut Stack) -> String {
+    let v = pop(stack);
+    match v.get_name() {
+        Some(name) => name.to_string(),
+        None => fail(
+            NOT_A_NAME,
+            format!("Expected a name to bind or look up, got {}", v.type_name()),
+        ),
+    }
+}
+
+/// The values that can be map keys. Integers are kept big so that equal
+/// integers are equal keys whichever representation they had.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MapKey {
+    Integer(BigInt),
+    Atom(String),
+    String(String),
+}
+
+impl MapKey {
+    pub fn from_value(v: &Value) -> MapKey {
+        match v {
+            Value::Atom(a) => MapKey::Atom(a.clone()),
+            Value::String(s) => MapKey::String(s.clone()),
+            v => MapKey::Integer(v.get_bigint().unwrap_or_else(|| {
+                fail(
+                    INVALID_KEY,
+                    format!("Can't use type {} as a map key", v.type_name()),
+                )
+            })),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
         match self {
-            Value::BuiltIn(_) => true,
-            _ => false,
+            MapKey::Integer(b) => Value::from_bigint(b.clone()),
+            MapKey::Atom(a) => Value::Atom(a.clone()),
+            MapKey::String(s) => Value::String(s.clone()),
         }
     }
 }
 
+impl Display for MapKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.to_value(), f)
+    }
+}
+
 // type Env = HashMap<String, Value>;
 type Env = ListEnv;
 
@@ -162,14 +375,14 @@ mod list {
                 List::new()
             } else {
                 List {
-                    head: Some(Rc::clone(&self.head.as_ref().unwrap())),
+                    head: Some(Rc::clone(self.head.as_ref().unwrap())),
                 }
             }
         }
 
         pub fn same_list(&self, l: &List<T>) -> bool {
             (self.is_empty() && l.is_empty())
-                || (Rc::ptr_eq(&self.head.as_ref().unwrap(), &l.head.as_ref().unwrap()))
+                || (Rc::ptr_eq(self.head.as_ref().unwrap(), l.head.as_ref().unwrap()))
         }
 
         pub fn find_map<B>(&self, f: impl Fn(&T) -> Option<B>) -> Option<B> {
@@ -263,6 +476,17 @@ impl ListEnv {
         self.0
             .find_map(|(k, v)| if key == k { Some(v.clone()) } else { None })
     }
+
+    /// Bindings besides the builtins
+    pub fn bindings(&self) -> usize {
+        self.0.iter().filter(|(_, v)| !v.is_builtin()).count()
+    }
+}
+
+impl Default for ListEnv {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PartialEq for ListEnv {
@@ -301,7 +525,7 @@ impl Debug for ListEnv {
 // }
 
 // fn builtin_force(env: &mut Env, stack: &mut Stack) {
-//     let t = stack.pop().expect("Stack empty").clone();
+//     let t = pop(stack).clone();
 //     call_value(env, stack, t);
 
 //     // if let Value::Thunk { env, fp } = t {
@@ -312,48 +536,408 @@ impl Debug for ListEnv {
 // }
 
 pub fn builtin_pop(env: &mut Env, stack: &mut Stack) {
-    let name = stack
-        .pop()
-        .expect("Stack empty")
-        .get_name()
-        .expect("Not a name")
-        .to_string();
+    let name = pop_name(stack);
 
-    let value = stack.pop().expect("Stack empty");
+    let value = pop(stack);
 
     env.insert(name, value);
 }
 
 pub fn builtin_push(env: &mut Env, stack: &mut Stack) {
-    let name = stack
-        .pop()
-        .expect("Stack empty")
-        .get_name()
-        .expect("Not a name")
-        .to_string();
+    let name = pop_name(stack);
 
     let value = env
         .get(&name)
-        .expect(&format!("Unbound name {name}"))
-        .clone();
+        .unwrap_or_else(|| fail(UNBOUND, format!("Unbound name {name} in env")));
 
     stack.push(value);
 }
 
-pub fn builtin_inc(_env: &mut Env, stack: &mut Stack) {
-    let v = stack.pop().expect("Stack empty");
+pub fn builtin_inc(env: &mut Env, stack: &mut Stack) {
+    stack.push(Value::Integer(1));
+    builtin_add(env, stack)
+}
 
-    let n = if let Some(i) = v.get_integer() {
-        i
-    } else {
-        panic!("Not integer: {:?}", v)
+fn numeric_op(
+    stack: &mut Stack,
+    small: fn(i64, i64) -> Option<i64>,
+    big: fn(&BigInt, &BigInt) -> BigInt,
+    float: fn(f64, f64) -> f64,
+) {
+    let b = pop(stack);
+    let a = pop(stack);
+
+    let r = match (a.get_integer(), b.get_integer()) {
+        (Some(x), Some(y)) => small(x, y).map(Value::Integer),
+        _ => None,
+    };
+
+    // Either a small operation overflowed or one side was already big or a
+    // float
+    let r = r.unwrap_or_else(|| {
+        if let (Value::Float(_), _) | (_, Value::Float(_)) = (&a, &b) {
+            let x = a.get_float().unwrap_or_else(|| type_mismatch("number", &a));
+            let y = b.get_float().unwrap_or_else(|| type_mismatch("number", &b));
+            return Value::Float(float(x, y));
+        }
+        let x = a
+            .get_bigint()
+            .unwrap_or_else(|| type_mismatch("integer", &a));
+        let y = b
+            .get_bigint()
+            .unwrap_or_else(|| type_mismatch("integer", &b));
+        Value::from_bigint(big(&x, &y))
+    });
+
+    stack.push(r)
+}
+
+pub fn builtin_add(_env: &mut Env, stack: &mut Stack) {
+    numeric_op(stack, i64::checked_add, |a, b| a + b, |a, b| a + b)
+}
+
+pub fn builtin_sub(_env: &mut Env, stack: &mut Stack) {
+    numeric_op(stack, i64::checked_sub, |a, b| a - b, |a, b| a - b)
+}
+
+pub fn builtin_mul(_env: &mut Env, stack: &mut Stack) {
+    numeric_op(stack, i64::checked_mul, |a, b| a * b, |a, b| a * b)
+}
+
+fn divide(a: &BigInt, b: &BigInt) -> (BigInt, BigInt) {
+    a.div_rem(b)
+        .unwrap_or_else(|| fail(DIVIDE_BY_ZERO, "Division by zero"))
+}
+
+pub fn builtin_div(_env: &mut Env, stack: &mut Stack) {
+    numeric_op(stack, i64::checked_div, |a, b| divide(a, b).0, |a, b| a / b)
+}
+
+pub fn builtin_rem(_env: &mut Env, stack: &mut Stack) {
+    numeric_op(stack, i64::checked_rem, |a, b| divide(a, b).1, |a, b| a % b)
+}
+
+pub fn builtin_float(_env: &mut Env, stack: &mut Stack) {
+    let v = pop(stack);
+
+    let x = v.get_float().unwrap_or_else(|| type_mismatch("number", &v));
+
+    stack.push(Value::Float(x))
+}
+
+fn to_integer(stack: &mut Stack, round: fn(f64) -> f64) {
+    let v = pop(stack);
+
+    let b = match v {
+        Value::Float(x) => BigInt::from_f64(round(x))
+            .unwrap_or_else(|| fail(NOT_FINITE, format!("Can't convert {v} to an integer"))),
+        v => v
+            .get_bigint()
+            .unwrap_or_else(|| type_mismatch("integer", &v)),
+    };
+
+    stack.push(Value::from_bigint(b))
+}
+
+pub fn builtin_truncate(_env: &mut Env, stack: &mut Stack) {
+    to_integer(stack, f64::trunc)
+}
+
+pub fn builtin_floor(_env: &mut Env, stack: &mut Stack) {
+    to_integer(stack, f64::floor)
+}
+
+pub fn builtin_ceil(_env: &mut Env, stack: &mut Stack) {
+    to_integer(stack, f64::ceil)
+}
+
+pub fn builtin_round(_env: &mut Env, stack: &mut Stack) {
+    to_integer(stack, f64::round)
+}
+
+pub fn builtin_thunk(env: &mut Env, stack: &mut Stack) {
+    let q = pop(stack);
+
+    match q {
+        Value::Quotation { fp, .. } => stack.push(Value::Thunk {
+            env: env.clone(),
+            fp,
+        }),
+        q => type_mismatch("quotation", &q),
+    }
+}
+
+/// Like `thunk`, but closes over the env of another thunk.
+pub fn builtin_thunk_in(_env: &mut Env, stack: &mut Stack) {
+    match pop(stack) {
+        Value::Thunk { mut env, .. } => builtin_thunk(&mut env, stack),
+        v => type_mismatch("thunk", &v),
+    }
+}
+
+fn get_items(v: &Value) -> &[Value] {
+    match v {
+        Value::Quotation { items, .. } => items,
+        Value::List(items) => items,
+        v => type_mismatch("quotation", v),
+    }
+}
+
+fn get_map(v: Value) -> Rc<BTreeMap<MapKey, Value>> {
+    match v {
+        Value::Map(map) => map,
+        v => type_mismatch("map", &v),
+    }
+}
+
+/// Pairs up alternating keys and values, later keys winning.
+pub fn map_from_items(items: Vec<Value>) -> Value {
+    let mut map = BTreeMap::new();
+    let mut items = items.into_iter();
+    while let Some(k) = items.next() {
+        let k = MapKey::from_value(&k);
+        let v = items.next().unwrap_or_else(|| {
+            fail(
+                MISSING_MAP_VALUE,
+                format!("Key {k} in map literal has no value"),
+            )
+        });
+        map.insert(k, v);
+    }
+    Value::Map(Rc::new(map))
+}
+
+fn get_list(v: Value) -> Rc<Vec<Value>> {
+    match v {
+        Value::List(items) => items,
+        v => type_mismatch("list", &v),
+    }
+}
+
+/// Collects everything above the innermost mark into a list.
+pub fn builtin_list_end(stack: &mut Stack, marks: &mut Vec<usize>) {
+    let mark = marks.pop().expect("List end without start");
+
+    let items = stack.split_off(mark.min(stack.len()));
+    stack.push(Value::List(Rc::new(items)))
+}
+
+/// Collects everything above the innermost mark into a map.
+pub fn builtin_map_end(stack: &mut Stack, marks: &mut Vec<usize>) {
+    let mark = marks.pop().expect("Map end without start");
+
+    let items = stack.split_off(mark.min(stack.len()));
+    stack.push(map_from_items(items))
+}
+
+pub fn builtin_get(_env: &mut Env, stack: &mut Stack) {
+    let k = MapKey::from_value(&pop(stack));
+    let map = get_map(pop(stack));
+
+    let v = map
+        .get(&k)
+        .unwrap_or_else(|| fail(KEY_NOT_FOUND, format!("Key {k} not found in map")));
+    stack.push(v.clone())
+}
+
+pub fn builtin_assoc(_env: &mut Env, stack: &mut Stack) {
+    let v = pop(stack);
+    let k = MapKey::from_value(&pop(stack));
+    let mut map = get_map(pop(stack));
+
+    Rc::make_mut(&mut map).insert(k, v);
+    stack.push(Value::Map(map))
+}
+
+pub fn builtin_dissoc(_env: &mut Env, stack: &mut Stack) {
+    let k = MapKey::from_value(&pop(stack));
+    let mut map = get_map(pop(stack));
+
+    Rc::make_mut(&mut map).remove(&k);
+    stack.push(Value::Map(map))
+}
+
+pub fn builtin_keys(_env: &mut Env, stack: &mut Stack) {
+    let map = get_map(pop(stack));
+
+    let keys = map.keys().map(MapKey::to_value).collect();
+    stack.push(Value::List(Rc::new(keys)))
+}
+
+pub fn builtin_contains(_env: &mut Env, stack: &mut Stack) {
+    let k = MapKey::from_value(&pop(stack));
+    let map = get_map(pop(stack));
+
+    let b = if map.contains_key(&k) { "t" } else { "f" };
+    stack.push(Value::Atom(b.to_string()))
+}
+
+pub fn builtin_append(_env: &mut Env, stack: &mut Stack) {
+    let v = pop(stack);
+    let mut items = get_list(pop(stack));
+
+    Rc::make_mut(&mut items).push(v);
+    stack.push(Value::List(items))
+}
+
+pub fn builtin_concat(_env: &mut Env, stack: &mut Stack) {
+    let b = get_list(pop(stack));
+    let mut a = get_list(pop(stack));
+
+    Rc::make_mut(&mut a).extend(b.iter().cloned());
+    stack.push(Value::List(a))
+}
+
+pub fn builtin_is_empty(_env: &mut Env, stack: &mut Stack) {
+    let items = get_list(pop(stack));
+
+    let b = if items.is_empty() { "t" } else { "f" };
+    stack.push(Value::Atom(b.to_string()))
+}
+
+pub fn builtin_uncons(_env: &mut Env, stack: &mut Stack) {
+    let items = get_list(pop(stack));
+
+    let first = items
+        .first()
+        .unwrap_or_else(|| fail(INDEX_OUT_OF_BOUNDS, "Index 0 out of bounds for length 0"))
+        .clone();
+    stack.push(Value::List(Rc::new(items[1..].to_vec())));
+    stack.push(first)
+}
+
+pub fn builtin_cswap(_env: &mut Env, stack: &mut Stack) {
+    if stack.len() < 3 {
+        fail(
+            STACK_UNDERFLOW,
+            format!(
+                "Needed 3 values on the stack, but there are only {}",
+                stack.len()
+            ),
+        )
+    }
+    let v = pop(stack);
+
+    if v == Value::Atom("t".to_string()) {
+        let len = stack.len();
+        stack.swap(len - 1, len - 2);
+    }
+}
+
+pub fn builtin_length(_env: &mut Env, stack: &mut Stack) {
+    let len = match pop(stack) {
+        Value::Map(map) => map.len(),
+        Value::Bytes(b) => b.len(),
+        q => get_items(&q).len(),
     };
 
-    stack.push(Value::Integer(n + 1))
+    stack.push(Value::Integer(len as i64))
+}
+
+pub fn builtin_nth(_env: &mut Env, stack: &mut Stack) {
+    let index = pop(stack);
+    let i = get_index(&index);
+    let q = pop(stack);
+
+    let (v, len) = match &q {
+        Value::Bytes(b) => (
+            i.and_then(|i| b.get(i)).map(|b| Value::Integer(*b as i64)),
+            b.len(),
+        ),
+        q => {
+            let items = get_items(q);
+            (i.and_then(|i| items.get(i)).cloned(), items.len())
+        }
+    };
+
+    stack.push(v.unwrap_or_else(|| {
+        fail(
+            INDEX_OUT_OF_BOUNDS,
+            format!("Index {index} out of bounds for length {len}"),
+        )
+    }))
+}
+
+/// None for integers that can't be an index, which errors show as given.
+fn get_index(v: &Value) -> Option<usize> {
+    let b = v
+        .get_bigint()
+        .unwrap_or_else(|| type_mismatch("integer", v));
+    b.to_i64().and_then(|i| usize::try_from(i).ok())
+}
+
+/// The elements from start up to but not including end of a list or bytes.
+pub fn builtin_slice(_env: &mut Env, stack: &mut Stack) {
+    let end = pop(stack);
+    let e = get_index(&end);
+    let start = pop(stack);
+    let s = get_index(&start);
+    let v = pop(stack);
+
+    let range = |len: usize| match (s, e) {
+        (Some(s), Some(e)) if s <= e && e <= len => s..e,
+        _ => fail(
+            INVALID_RANGE,
+            format!("Range {start}..{end} out of bounds for length {len}"),
+        ),
+    };
+
+    stack.push(match v {
+        Value::Bytes(b) => Value::Bytes(b[range(b.len())].to_vec()),
+        v => {
+            let items = get_list(v);
+            Value::List(Rc::new(items[range(items.len())].to_vec()))
+        }
+    })
+}
+
+pub fn builtin_char_to_integer(_env: &mut Env, stack: &mut Stack) {
+    match pop(stack) {
+        Value::Char(c) => stack.push(Value::Integer(c as i64)),
+        v => type_mismatch("char", &v),
+    }
+}
+
+pub fn builtin_integer_to_char(_env: &mut Env, stack: &mut Stack) {
+    let v = pop(stack);
+
+    let c = v
+        .get_bigint()
+        .unwrap_or_else(|| type_mismatch("integer", &v))
+        .to_i64()
+        .and_then(|i| u32::try_from(i).ok())
+        .and_then(char::from_u32)
+        .unwrap_or_else(|| fail(INVALID_CHAR, format!("{v} is not a valid character")));
+
+    stack.push(Value::Char(c))
+}
+
+pub fn builtin_bytes_to_list(_env: &mut Env, stack: &mut Stack) {
+    match pop(stack) {
+        Value::Bytes(b) => stack.push(Value::List(Rc::new(
+            b.iter().map(|b| Value::Integer(*b as i64)).collect(),
+        ))),
+        v => type_mismatch("bytes", &v),
+    }
+}
+
+pub fn builtin_list_to_bytes(_env: &mut Env, stack: &mut Stack) {
+    let items = get_list(pop(stack));
+
+    let b = items
+        .iter()
+        .map(|v| {
+            v.get_integer()
+                .filter(|i| (0..=255).contains(i))
+                .unwrap_or_else(|| fail(NOT_A_BYTE, format!("{v} is not a byte"))) as u8
+        })
+        .collect();
+
+    stack.push(Value::Bytes(b))
 }
 
 pub fn builtin_println(_env: &mut Env, stack: &mut Stack) {
-    let v = stack.pop().expect("Stack empty");
+    let v = pop(stack);
 
     println!("{v}");
 
@@ -369,11 +953,84 @@ pub fn make_env() -> Env {
     env.insert("pop".to_string(), Value::BuiltIn(builtin_pop));
     env.insert("push".to_string(), Value::BuiltIn(builtin_push));
     env.insert("inc".to_string(), Value::BuiltIn(builtin_inc));
+    env.insert("+".to_string(), Value::BuiltIn(builtin_add));
+    env.insert("-".to_string(), Value::BuiltIn(builtin_sub));
+    env.insert("*".to_string(), Value::BuiltIn(builtin_mul));
+    env.insert("/".to_string(), Value::BuiltIn(builtin_div));
+    env.insert("%".to_string(), Value::BuiltIn(builtin_rem));
+    env.insert("float".to_string(), Value::BuiltIn(builtin_float));
+    env.insert("truncate".to_string(), Value::BuiltIn(builtin_truncate));
+    env.insert("floor".to_string(), Value::BuiltIn(builtin_floor));
+    env.insert("ceil".to_string(), Value::BuiltIn(builtin_ceil));
+    env.insert("round".to_string(), Value::BuiltIn(builtin_round));
+    env.insert("thunk".to_string(), Value::BuiltIn(builtin_thunk));
+    env.insert("thunk-in".to_string(), Value::BuiltIn(builtin_thunk_in));
+    env.insert("length".to_string(), Value::BuiltIn(builtin_length));
+    env.insert("nth".to_string(), Value::BuiltIn(builtin_nth));
+    env.insert("append".to_string(), Value::BuiltIn(builtin_append));
+    env.insert("concat".to_string(), Value::BuiltIn(builtin_concat));
+    env.insert("empty?".to_string(), Value::BuiltIn(builtin_is_empty));
+    env.insert("uncons".to_string(), Value::BuiltIn(builtin_uncons));
+    env.insert("cswap".to_string(), Value::BuiltIn(builtin_cswap));
+    env.insert("slice".to_string(), Value::BuiltIn(builtin_slice));
+    env.insert(
+        "char->integer".to_string(),
+        Value::BuiltIn(builtin_char_to_integer),
+    );
+    env.insert(
+        "integer->char".to_string(),
+        Value::BuiltIn(builtin_integer_to_char),
+    );
+    env.insert(
+        "bytes->list".to_string(),
+        Value::BuiltIn(builtin_bytes_to_list),
+    );
+    env.insert(
+        "list->bytes".to_string(),
+        Value::BuiltIn(builtin_list_to_bytes),
+    );
+    env.insert("get".to_string(), Value::BuiltIn(builtin_get));
+    env.insert("assoc".to_string(), Value::BuiltIn(builtin_assoc));
+    env.insert("dissoc".to_string(), Value::BuiltIn(builtin_dissoc));
+    env.insert("keys".to_string(), Value::BuiltIn(builtin_keys));
+    env.insert("contains?".to_string(), Value::BuiltIn(builtin_contains));
     env.insert("println".to_string(), Value::BuiltIn(builtin_println));
 
     env
 }
 
+thread_local! {
+    /// The entry in `SPANS` of the instruction that last set it
+    pub static AT: Cell<usize> = const { Cell::new(usize::MAX) };
+}
+
+/// `path:line:col` of the instruction that set `AT` last, if any did.
+pub fn location() -> Option<String> {
+    let (file, line, col) = SPANS.get(AT.get())?;
+    Some(format!("{}:{line}:{col}", FILES[*file as usize]))
+}
+
+/// Reports a runtime error at the place in the source that caused it,
+/// rather than the place in the generated code. Errors the interpreter has
+/// too are shown with its code.
+pub fn report_panic(info: &std::panic::PanicHookInfo) {
+    let payload = info.payload();
+    if let Some(e) = payload.downcast_ref::<Error>() {
+        eprintln!("error[F{:04}]: {}", e.code, e.message);
+    } else {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+            .unwrap_or("panicked");
+        eprintln!("error: {message}");
+    }
+    if let Some(l) = location() {
+        eprintln!(" --> {l}");
+    }
+}
+
+#[allow(dead_code)]
 pub struct Frame {
     tr: ThunkRef,
     env: Env,
@@ -385,12 +1042,10 @@ pub fn builtin_force_cc(stack: &mut Stack, cur_frame: &mut Frame) -> Frame {
     match th {
         Value::Thunk { env, fp } => {
             stack.push(cc);
-            let nf = Frame {
+            Frame {
                 env: env.clone(),
                 tr: fp,
-            };
-            return nf;
-            // cs.push(nf);
+            }
         }
         Value::BuiltIn(f) => {
             f(&mut cur_frame.env, stack);
@@ -399,12 +1054,10 @@ pub fn builtin_force_cc(stack: &mut Stack, cur_frame: &mut Frame) -> Frame {
                 fp: cc_fp,
             } = cc
             {
-                let nf = Frame {
+                Frame {
                     env: cc_env.clone(),
                     tr: cc_fp,
-                };
-                return nf;
-                // cs.push(nf);
+                }
             } else {
                 panic!(
                     "Forcing builtin with CC, expected CC to be thunk, got {:?}",
@@ -412,20 +1065,24 @@ pub fn builtin_force_cc(stack: &mut Stack, cur_frame: &mut Frame) -> Frame {
                 )
             }
         }
-        x => panic!("Can't force non-thunk: {:?}", x),
+        x => not_applicable(&x),
     }
 }
 
+fn not_applicable(v: &Value) -> ! {
+    fail(
+        INVALID_APPLY,
+        format!("Can't apply type {} as a function", v.type_name()),
+    )
+}
+
 pub fn builtin_force_cc_bare(stack: &mut Stack) -> Frame {
     let cc = stack.pop().unwrap();
     match cc {
-        Value::Thunk { env, fp } => {
-            let nf = Frame {
-                env: env.clone(),
-                tr: fp,
-            };
-            return nf;
-        }
-        x => panic!("Can't force non-thunk: {:?}", x),
+        Value::Thunk { env, fp } => Frame {
+            env: env.clone(),
+            tr: fp,
+        },
+        x => not_applicable(&x),
     }
 }
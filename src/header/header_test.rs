@@ -1,4 +1,6 @@
+use super::bigint::BigInt;
 use super::header::*;
+use std::rc::Rc;
 
 #[test]
 fn test_env() {
@@ -7,3 +9,238 @@ fn test_env() {
     env.insert("test".to_string(), Value::Integer(1));
     assert_eq!(Some(Value::Integer(1)), env.get("test"));
 }
+
+#[test]
+fn test_bigint() {
+    let big = |i: i64| BigInt::from_i64(i);
+
+    assert_eq!(big(0).to_string(), "0");
+    assert_eq!(big(-42).to_string(), "-42");
+    assert_eq!(big(i64::MIN).to_i64(), Some(i64::MIN));
+    assert_eq!(big(i64::MAX).to_i64(), Some(i64::MAX));
+
+    let m = &big(i64::MAX) + &big(1);
+    assert_eq!(m.to_string(), "9223372036854775808");
+    assert_eq!(m.to_i64(), None);
+    assert_eq!((&m - &big(1)).to_i64(), Some(i64::MAX));
+    assert_eq!((-&m).to_i64(), Some(i64::MIN));
+
+    let p = &(&m * &m) * &big(-1000000007);
+    assert_eq!(
+        p.to_string(),
+        "-85070592325728757977485962918847615869594370048"
+    );
+    let (q, r) = p.div_rem(&big(1000000007)).unwrap();
+    assert_eq!(q, -&(&m * &m));
+    assert!(r.is_zero());
+
+    let (q, r) = big(-7).div_rem(&big(2)).unwrap();
+    assert_eq!((q.to_i64(), r.to_i64()), (Some(-3), Some(-1)));
+    assert_eq!(big(1).div_rem(&big(0)), None);
+
+    assert!(big(-5) < big(3));
+    assert!(m > big(i64::MAX));
+    assert_eq!(m.to_f64(), 9223372036854775808.0);
+
+    assert_eq!(BigInt::from_f64(-2.75), Some(big(-2)));
+    assert_eq!(BigInt::from_f64(9223372036854775808.0), Some(m.clone()));
+    assert_eq!(
+        BigInt::from_f64(-1e30).unwrap().to_string(),
+        "-1000000000000000019884624838656"
+    );
+    assert_eq!(BigInt::from_f64(f64::NAN), None);
+    assert_eq!(BigInt::from_f64(1e300).unwrap().to_f64(), 1e300);
+}
+
+#[test]
+fn test_integer_overflow() {
+    let mut env = make_env();
+    let mut stack = vec![Value::Integer(i64::MAX), Value::Integer(2)];
+    builtin_mul(&mut env, &mut stack);
+    assert_eq!(stack[0].to_string(), "18446744073709551614");
+
+    stack.push(Value::Integer(2));
+    builtin_div(&mut env, &mut stack);
+    assert_eq!(stack, vec![Value::Integer(i64::MAX)]);
+}
+
+#[test]
+fn test_float() {
+    let mut env = make_env();
+    let mut stack = vec![Value::Integer(3), Value::Float(0.5)];
+    builtin_mul(&mut env, &mut stack);
+    assert_eq!(stack, vec![Value::Float(1.5)]);
+
+    builtin_floor(&mut env, &mut stack);
+    assert_eq!(stack, vec![Value::Integer(1)]);
+
+    let shown: Vec<String> = [1.0, 1e100, f64::NEG_INFINITY, f64::NAN]
+        .into_iter()
+        .map(|x| Value::Float(x).to_string())
+        .collect();
+    assert_eq!(shown, vec!["1.0", "1e100", "#-inf", "#nan"]);
+}
+
+#[test]
+fn test_list() {
+    let list = |vs: Vec<i64>| Value::List(Rc::new(vs.into_iter().map(Value::Integer).collect()));
+    let mut env = make_env();
+
+    let mut stack = vec![Value::Integer(0), Value::Integer(1), Value::Integer(2)];
+    let mut marks = vec![1];
+    builtin_list_end(&mut stack, &mut marks);
+    assert_eq!(stack, vec![Value::Integer(0), list(vec![1, 2])]);
+    assert!(marks.is_empty());
+
+    let shared = list(vec![1]);
+    let mut stack = vec![shared.clone(), Value::Integer(2)];
+    builtin_append(&mut env, &mut stack);
+    stack.push(list(vec![3]));
+    builtin_concat(&mut env, &mut stack);
+    assert_eq!(stack, vec![list(vec![1, 2, 3])]);
+    assert_eq!(shared, list(vec![1]));
+    assert_eq!(stack[0].to_string(), "[ 1 2 3 ]");
+
+    builtin_uncons(&mut env, &mut stack);
+    assert_eq!(stack, vec![list(vec![2, 3]), Value::Integer(1)]);
+
+    let mut stack = vec![list(vec![])];
+    builtin_is_empty(&mut env, &mut stack);
+    assert_eq!(stack, vec![Value::Atom("t".to_string())]);
+}
+
+#[test]
+fn test_map() {
+    let atom = |a: &str| Value::Atom(a.to_string());
+    let mut env = make_env();
+
+    let m = map_from_items(vec![
+        atom("b"),
+        Value::Integer(2),
+        Value::Integer(1),
+        atom("one"),
+        atom("b"),
+        Value::Integer(3),
+    ]);
+    assert_eq!(m.to_string(), "{ 1 'one 'b 3 }");
+
+    let mut stack = vec![m.clone(), atom("c"), Value::Integer(4)];
+    builtin_assoc(&mut env, &mut stack);
+    stack.push(Value::Integer(1));
+    builtin_dissoc(&mut env, &mut stack);
+    assert_eq!(stack[0].to_string(), "{ 'b 3 'c 4 }");
+    assert_eq!(m.to_string(), "{ 1 'one 'b 3 }");
+
+    let m = stack.pop().unwrap();
+
+    let mut stack = vec![m.clone(), atom("c")];
+    builtin_contains(&mut env, &mut stack);
+    assert_eq!(stack, vec![atom("t")]);
+
+    let mut stack = vec![m.clone(), atom("c")];
+    builtin_get(&mut env, &mut stack);
+    assert_eq!(stack, vec![Value::Integer(4)]);
+
+    let mut stack = vec![m];
+    builtin_keys(&mut env, &mut stack);
+    assert_eq!(stack[0].to_string(), "[ 'b 'c ]");
+}
+
+#[test]
+fn test_char_and_bytes() {
+    let mut env = make_env();
+
+    let mut stack = vec![Value::Char('λ')];
+    builtin_char_to_integer(&mut env, &mut stack);
+    builtin_integer_to_char(&mut env, &mut stack);
+    assert_eq!(stack, vec![Value::Char('λ')]);
+
+    let b = Value::Bytes(vec![0, b'a', b'"', 0xff]);
+    assert_eq!(b.to_string(), r#"b"\x00a\"\xff""#);
+
+    let mut stack = vec![b.clone(), Value::Integer(1), Value::Integer(3)];
+    builtin_slice(&mut env, &mut stack);
+    assert_eq!(stack, vec![Value::Bytes(vec![b'a', b'"'])]);
+
+    let mut stack = vec![b.clone(), Value::Integer(3)];
+    builtin_nth(&mut env, &mut stack);
+    assert_eq!(stack, vec![Value::Integer(255)]);
+
+    let mut stack = vec![b.clone()];
+    builtin_bytes_to_list(&mut env, &mut stack);
+    builtin_list_to_bytes(&mut env, &mut stack);
+    assert_eq!(stack, vec![b]);
+}
+
+/// The error `f` stops the program with on `stack`.
+fn error(f: fn(&mut ListEnv, &mut Vec<Value>), mut stack: Vec<Value>) -> Error {
+    let mut env = make_env();
+    let payload =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(&mut env, &mut stack)))
+            .unwrap_err();
+    payload.downcast_ref::<Error>().unwrap().clone()
+}
+
+#[test]
+fn test_errors() {
+    use crate::error_codes;
+
+    let codes = [
+        (UNBOUND, error_codes::UNBOUND),
+        (INVALID_APPLY, error_codes::INVALID_APPLY),
+        (POP_EMPTY, error_codes::POP_EMPTY),
+        (TYPE_MISMATCH, error_codes::TYPE_MISMATCH),
+        (DIVIDE_BY_ZERO, error_codes::DIVIDE_BY_ZERO),
+        (NOT_FINITE, error_codes::NOT_FINITE),
+        (INDEX_OUT_OF_BOUNDS, error_codes::INDEX_OUT_OF_BOUNDS),
+        (INVALID_KEY, error_codes::INVALID_KEY),
+        (KEY_NOT_FOUND, error_codes::KEY_NOT_FOUND),
+        (MISSING_MAP_VALUE, error_codes::MISSING_MAP_VALUE),
+        (INVALID_RANGE, error_codes::INVALID_RANGE),
+        (INVALID_CHAR, error_codes::INVALID_CHAR),
+        (NOT_A_BYTE, error_codes::NOT_A_BYTE),
+        (NOT_A_NAME, error_codes::NOT_A_NAME),
+        (STACK_UNDERFLOW, error_codes::STACK_UNDERFLOW),
+    ];
+    for (ours, theirs) in codes {
+        assert_eq!(ours, theirs.0);
+    }
+
+    let e = error(builtin_uncons, vec![Value::List(Rc::new(vec![]))]);
+    assert_eq!(e.code, INDEX_OUT_OF_BOUNDS);
+    assert_eq!(e.message, "Index 0 out of bounds for length 0");
+
+    let e = error(builtin_add, vec![Value::Integer(1)]);
+    assert_eq!(e.code, POP_EMPTY);
+
+    let e = error(
+        builtin_div,
+        vec![Value::Float(1.0), Value::Atom("a".to_string())],
+    );
+    assert_eq!(e.message, "Type mismatch, expected number, got atom");
+
+    let e = error(
+        builtin_cswap,
+        vec![Value::Integer(1), Value::Atom("t".to_string())],
+    );
+    assert_eq!(e.code, STACK_UNDERFLOW);
+    assert_eq!(
+        e.message,
+        "Needed 3 values on the stack, but there are only 2"
+    );
+}
+
+#[test]
+fn test_index_errors() {
+    let b = Value::Bytes(vec![1, 2]);
+
+    let e = error(builtin_nth, vec![b.clone(), Value::Integer(-1)]);
+    assert_eq!(e.message, "Index -1 out of bounds for length 2");
+
+    let e = error(
+        builtin_slice,
+        vec![b, Value::Integer(-1), Value::Integer(1)],
+    );
+    assert_eq!(e.code, INVALID_RANGE);
+    assert_eq!(e.message, "Range -1..1 out of bounds for length 2");
+}
@@ -1,3 +1,5 @@
+pub mod bigint;
+#[allow(clippy::module_inception)]
 pub mod header;
 
 #[cfg(test)]
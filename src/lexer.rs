@@ -0,0 +1,453 @@
+//! A handwritten lexer that reads the same syntax as `parser::parser`, one
+//! token at a time and without recursion, for `reader`.
+
+use chumsky::error::{Error as _, Simple};
+
+use crate::parser::{
+    is_atom_start, is_delimiter, parse_char, parse_float, parse_integer, AtomMod, FileId, Pragma,
+    Span,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Integer(i64),
+    Float(f64),
+    Atom(Option<AtomMod>, String),
+    String(String),
+    Char(char),
+    Bytes(Vec<u8>),
+    /// A `'` that quotes the thunk, list or map after it
+    Quote,
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    LeftBrace,
+    RightBrace,
+    Pragma(Pragma),
+}
+
+pub struct Lexer {
+    file: FileId,
+    chars: Vec<char>,
+    pos: usize,
+    /// Whether a shebang or pragma could still come next
+    at_start: bool,
+}
+
+type LexResult = Result<(Token, Span), Simple<char>>;
+
+impl Lexer {
+    pub fn new(file: FileId, src: &str) -> Self {
+        Lexer {
+            file,
+            chars: src.chars().collect(),
+            pos: 0,
+            at_start: true,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.chars.get(self.pos + n).copied()
+    }
+
+    fn span(&self, start: usize) -> Span {
+        Span::new(self.file, start..self.pos)
+    }
+
+    fn unexpected(&self, expected: &[Option<char>]) -> Simple<char> {
+        let found = self.peek();
+        let end = if found.is_some() {
+            self.pos + 1
+        } else {
+            self.pos
+        };
+        Simple::expected_input_found(self.pos..end, expected.iter().copied(), found)
+    }
+
+    fn skip_trivia(&mut self) -> Result<(), Simple<char>> {
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some(c), _) if c.is_whitespace() => self.pos += 1,
+                (Some(';'), _) => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.pos += 1;
+                    }
+                }
+                (Some('#'), Some('|')) => {
+                    let mut depth = 0;
+                    loop {
+                        match (self.peek(), self.peek_at(1)) {
+                            (Some('#'), Some('|')) => {
+                                depth += 1;
+                                self.pos += 2;
+                            }
+                            (Some('|'), Some('#')) => {
+                                depth -= 1;
+                                self.pos += 2;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            (Some(_), _) => self.pos += 1,
+                            (None, _) => {
+                                return Err(self
+                                    .unexpected(&[Some('|')])
+                                    .with_label("block comment"))
+                            }
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Up to the next delimiter
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !is_delimiter(c)) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn line_rest(&mut self, end: &[char]) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !end.contains(&c)) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Whatever follows a bad token up to the next delimiter, so lexing can
+    /// carry on after an error.
+    fn recover(&mut self) {
+        self.word();
+    }
+
+    /// Past the closing quote of a string with a bad escape in it, so the
+    /// rest of it isn't read as code.
+    fn skip_string(&mut self) {
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '"' => return,
+                '\\' if self.peek().is_some() => self.pos += 1,
+                _ => {}
+            }
+        }
+    }
+
+    fn number_or_atom(&mut self, start: usize, m: Option<AtomMod>) -> LexResult {
+        let w = self.word();
+        let custom = |e: String| Simple::custom(start..self.pos, e);
+        let token = match (m, parse_integer(&w), parse_float(&w)) {
+            (None, Some(i), _) => Token::Integer(i.map_err(custom)?),
+            (None, None, Some(f)) => Token::Float(f.map_err(custom)?),
+            (Some(_), Some(Err(e)), _) | (Some(_), _, Some(Err(e))) => return Err(custom(e)),
+            (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+                return Err(custom("expected atom, found number".to_string()))
+            }
+            (m, None, None) => Token::Atom(m, w),
+        };
+        Ok((token, self.span(start)))
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, Simple<char>> {
+        if self.peek() != Some('{') {
+            return Err(self.unexpected(&[Some('{')]));
+        }
+        self.pos += 1;
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) && self.pos - start < 6 {
+            self.pos += 1;
+        }
+        if self.pos == start || self.peek() != Some('}') {
+            return Err(self.unexpected(&[Some('}')]));
+        }
+        let hex: String = self.chars[start..self.pos].iter().collect();
+        self.pos += 1;
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| Simple::custom(start - 1..self.pos, "invalid unicode escape"))
+    }
+
+    fn string(&mut self, start: usize) -> LexResult {
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.unexpected(&[Some('"')])),
+                Some('"') => break,
+                Some('\\') => {
+                    self.pos += 1;
+                    let c = match self.peek() {
+                        Some(c @ ('\\' | '"')) => c,
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('0') => '\0',
+                        Some('u') => {
+                            self.pos += 1;
+                            s.push(self.unicode_escape()?);
+                            continue;
+                        }
+                        _ => return Err(self.unexpected(&[])),
+                    };
+                    s.push(c);
+                }
+                Some(c) => s.push(c),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        Ok((Token::String(s), self.span(start)))
+    }
+
+    fn bytes(&mut self, start: usize) -> LexResult {
+        self.pos += 2;
+        let mut b = vec![];
+        loop {
+            match self.peek() {
+                None => return Err(self.unexpected(&[Some('"')])),
+                Some('"') => break,
+                Some('\\') => {
+                    self.pos += 1;
+                    let byte = match self.peek() {
+                        Some('\\') => b'\\',
+                        Some('"') => b'"',
+                        Some('n') => b'\n',
+                        Some('r') => b'\r',
+                        Some('t') => b'\t',
+                        Some('0') => b'\0',
+                        Some('x') => {
+                            let hex: String = self.chars
+                                [self.pos + 1..(self.pos + 3).min(self.chars.len())]
+                                .iter()
+                                .collect();
+                            match u8::from_str_radix(&hex, 16) {
+                                Ok(byte)
+                                    if hex.len() == 2
+                                        && hex.chars().all(|c| c.is_ascii_hexdigit()) =>
+                                {
+                                    self.pos += 2;
+                                    byte
+                                }
+                                _ => {
+                                    self.pos += 1;
+                                    return Err(self.unexpected(&[]));
+                                }
+                            }
+                        }
+                        _ => return Err(self.unexpected(&[])),
+                    };
+                    b.push(byte);
+                }
+                Some(c) if !c.is_ascii() => {
+                    return Err(Simple::custom(
+                        self.pos..self.pos + 1,
+                        "non-ASCII character in byte string",
+                    ))
+                }
+                Some(c) => b.push(c as u8),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        Ok((Token::Bytes(b), self.span(start)))
+    }
+
+    fn hash(&mut self, start: usize) -> LexResult {
+        match self.peek_at(1) {
+            Some('\\') if self.peek_at(2).is_some() => {
+                self.pos += 3;
+                let s: String = std::iter::once(self.chars[start + 2])
+                    .chain(self.word().chars())
+                    .collect();
+                let c = parse_char(&s).map_err(|e| Simple::custom(start..self.pos, e))?;
+                Ok((Token::Char(c), self.span(start)))
+            }
+            // Input that stops before the char, which more could finish
+            Some('\\') => {
+                self.pos += 2;
+                Err(self.unexpected(&[]).with_label("char"))
+            }
+            Some(c) if is_atom_start(c) => {
+                self.pos += 1;
+                let w = self.word();
+                let f = match w.as_str() {
+                    "inf" => f64::INFINITY,
+                    "-inf" => f64::NEG_INFINITY,
+                    "nan" => f64::NAN,
+                    _ => {
+                        return Err(Simple::custom(
+                            start..self.pos,
+                            "expected #inf, #-inf or #nan",
+                        ))
+                    }
+                };
+                Ok((Token::Float(f), self.span(start)))
+            }
+            _ => {
+                self.pos += 1;
+                Err(self.unexpected(&[]))
+            }
+        }
+    }
+
+    fn pragma(&mut self, start: usize) -> LexResult {
+        self.pos += "#frospy".len();
+        let rest = self.line_rest(&['\n', ';']);
+        let mut words = rest.split_whitespace();
+        let version = words
+            .next()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| Simple::custom(start..self.pos, "expected language version"))?;
+        let pragma = Pragma {
+            version,
+            extensions: words.map(|w| w.to_string()).collect(),
+        };
+        Ok((Token::Pragma(pragma), self.span(start)))
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.peek_at(i) == Some(c))
+    }
+
+    fn token(&mut self) -> Option<LexResult> {
+        if self.pos == 0 && self.starts_with("#!") {
+            self.line_rest(&['\n']);
+        }
+        if let Err(e) = self.skip_trivia() {
+            return Some(Err(e));
+        }
+        let at_start = std::mem::replace(&mut self.at_start, false);
+
+        let start = self.pos;
+        let c = self.peek()?;
+        let single = |t: Token, l: &mut Lexer| {
+            l.pos += 1;
+            Ok((t, l.span(start)))
+        };
+        Some(match c {
+            '(' => single(Token::LeftParen, self),
+            ')' => single(Token::RightParen, self),
+            '[' => single(Token::LeftBracket, self),
+            ']' => single(Token::RightBracket, self),
+            '{' => single(Token::LeftBrace, self),
+            '}' => single(Token::RightBrace, self),
+            '"' => self.string(start).inspect_err(|_| self.skip_string()),
+            'b' if self.peek_at(1) == Some('"') => {
+                self.bytes(start).inspect_err(|_| self.skip_string())
+            }
+            '#' if at_start && self.starts_with("#frospy") => self.pragma(start),
+            '#' => self.hash(start),
+            '\'' | '$' | '^' => {
+                let m = match c {
+                    '\'' => AtomMod::Quote,
+                    '$' => AtomMod::QuotePop,
+                    _ => AtomMod::QuotePush,
+                };
+                self.pos += 1;
+                match self.peek() {
+                    Some(c) if is_atom_start(c) => self.number_or_atom(start, Some(m)),
+                    _ if m == AtomMod::Quote => match self.skip_trivia() {
+                        Err(e) => Err(e),
+                        Ok(()) if matches!(self.peek(), Some('(' | '[' | '{')) => {
+                            Ok((Token::Quote, Span::new(self.file, start..start + 1)))
+                        }
+                        Ok(()) => Err(self
+                            .unexpected(&[Some('('), Some('['), Some('{')])
+                            .with_label("quotation")),
+                    },
+                    _ => Err(self.unexpected(&[]).with_label("atom")),
+                }
+            }
+            c if is_atom_start(c) => self.number_or_atom(start, None),
+            // Only `;` and whitespace are left, and trivia took those
+            _ => unreachable!("{c:?} can start a token"),
+        })
+    }
+}
+
+impl Iterator for Lexer {
+    type Item = LexResult;
+
+    /// The next token, or an error after which lexing carries on from the
+    /// next delimiter.
+    fn next(&mut self) -> Option<Self::Item> {
+        let t = self.token()?;
+        if t.is_err() {
+            self.recover();
+        }
+        Some(t)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tokens(src: &str) -> Vec<Token> {
+        Lexer::new(FileId::default(), src)
+            .map(|t| t.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn test_lexer() {
+        assert_eq!(
+            tokens("#!/bin/frospy\n#frospy 1 ; c\n(a $b) '[1 2.5] #| x |# \"s\" b\"\\x00\" #\\a"),
+            vec![
+                Token::Pragma(Pragma {
+                    version: 1,
+                    extensions: vec![]
+                }),
+                Token::LeftParen,
+                Token::Atom(None, "a".to_string()),
+                Token::Atom(Some(AtomMod::QuotePop), "b".to_string()),
+                Token::RightParen,
+                Token::Quote,
+                Token::LeftBracket,
+                Token::Integer(1),
+                Token::Float(2.5),
+                Token::RightBracket,
+                Token::String("s".to_string()),
+                Token::Bytes(vec![0]),
+                Token::Char('a'),
+            ]
+        );
+
+        let spans: Vec<_> = Lexer::new(FileId::default(), "' (x) 'y")
+            .map(|t| t.unwrap().1.range)
+            .collect();
+        assert_eq!(spans, vec![0..1, 2..3, 3..4, 4..5, 6..8]);
+
+        let results: Vec<_> = Lexer::new(FileId::default(), "1 '12 99999999999999999999 x")
+            .map(|t| t.map(|(t, _)| t).map_err(|e| e.span()))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                Ok(Token::Integer(1)),
+                Err(2..5),
+                Err(6..26),
+                Ok(Token::Atom(None, "x".to_string())),
+            ]
+        );
+
+        // The rest of a string with a bad escape is skipped
+        let results: Vec<_> = Lexer::new(FileId::default(), r#""a\q \" (" b"λ" 2"#)
+            .map(|t| t.map(|(t, _)| t).map_err(|e| e.span()))
+            .collect();
+        assert_eq!(results, vec![Err(3..4), Err(13..14), Ok(Token::Integer(2))]);
+    }
+}
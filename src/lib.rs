@@ -6,54 +6,18 @@ use rpds::HashTrieMap;
 pub mod compiler;
 pub mod compiler2;
 pub mod cps;
+pub mod cst;
+pub mod delimiters;
+pub mod diagnostic;
+pub mod error_codes;
 pub mod header;
+pub mod lexer;
 pub mod parser;
+pub mod reader;
+pub mod source_map;
+pub mod trace;
 pub mod util;
 
-// #[derive(Debug, PartialEq, Clone, Eq, Hash)]
-// enum Token {
-//     Integer(i64),
-//     Atom(Option<AtomMod>, String),
-//     LeftParen,
-//     RightParen,
-// }
-
-// fn tokenizer<'a>() -> impl Parser<char, Vec<Token>, Error = Simple<char>> {
-//     let atom = choice((
-//         just('\'').to(AtomMod::Quote),
-//         just('$').to(AtomMod::QuotePop),
-//         just('^').to(AtomMod::QuotePush),
-//     ))
-//     .or_not()
-//     .then(text::ident())
-//     .map(|(m, s)| -> Token { Token::Atom(m, s) });
-
-//     choice((
-//         primitive::just('(').to(Token::LeftParen),
-//         primitive::just(')').to(Token::RightParen),
-//         ,
-//         atom,
-//     ))
-//     .padded()
-//     .repeated()
-//     .then_ignore(end())
-// }
-
-// #[cfg(test)]
-// mod test_tokenizer {
-//     use super::*;
-
-//     #[test]
-//     fn test_tokenizer() {
-//         let s = "(fn) 'a $fn force 40".to_string();
-//         let tokens = tokenizer().parse(s);
-
-//         dbg!(tokens);
-
-//         assert!(false);
-//     }
-// }
-
 // #[derive(Debug)]
 // struct AST {
 //     elements: Vec<ASTElement>,
@@ -64,15 +28,21 @@ type Env = HashTrieMap<String, Value>;
 #[derive(Debug, Clone)]
 enum Value {
     Integer(i64),
+    Float(f64),
     Atom(String),
+    String(String),
+    Quotation(Vec<Expr>),
     Thunk { env: Env, exprs: Vec<Expr> },
 }
 impl Value {
     fn from_quoted_expr(e: &Expr) -> Self {
         match e {
             Expr::Integer(i, _) => Value::Integer(*i),
+            Expr::Float(x, _) => Value::Float(*x),
             Expr::Atom(a, _) => Value::Atom(a.to_string()),
-            Expr::Thunk(_, _) => panic!("Can't get quote of tunk"),
+            Expr::String(s, _) => Value::String(s.to_string()),
+            Expr::Thunk(es, _) => Value::Quotation(es.to_vec()),
+            e => panic!("Can't get quote of {e}"),
         }
     }
 
@@ -88,7 +58,16 @@ impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Integer(i) => f.write_fmt(format_args!("{}", i)),
+            Value::Float(x) => f.write_str(&parser::float_literal(*x)),
             Value::Atom(s) => f.write_str(s),
+            Value::String(s) => f.write_str(s),
+            Value::Quotation(exprs) => {
+                f.write_str("'( ")?;
+                for e in exprs.iter() {
+                    f.write_fmt(format_args!("{} ", e))?;
+                }
+                f.write_str(")")
+            }
             Value::Thunk { env, exprs } => {
                 f.write_str("( ")?;
                 for e in exprs.iter() {
@@ -178,11 +157,13 @@ impl Ctx {
         }
 
         // Safe because if the frame is done, we've already returned
-        let e = cf.get_expr().unwrap();
+        let e = cf.get_expr().unwrap().clone();
 
-        match e.clone() {
+        match &e {
             // get_env_mut needs access to the ctx, but e borrows.
-            Expr::Integer(i, _) => self.stack.push(Value::Integer(i)),
+            Expr::Integer(i, _) => self.stack.push(Value::Integer(*i)),
+            Expr::Float(x, _) => self.stack.push(Value::Float(*x)),
+            Expr::String(s, _) => self.stack.push(Value::String(s.clone())),
             Expr::Atom(a, _) => match a.as_str() {
                 "quote" => {
                     let ev = cf.get_expr().expect("Can't quote missing expr");
@@ -251,6 +232,7 @@ impl Ctx {
 
                 self.stack.push(t);
             }
+            e => panic!("{e} isn't supported here"),
         }
 
         false
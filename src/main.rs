@@ -1,89 +1,245 @@
-use std::io;
+use std::{fs, io, path::PathBuf, process};
 
-use chumsky::Parser;
-use clap::{Parser as ClapParser, Subcommand};
+use clap::{Args, Parser as ClapParser, Subcommand};
 use frospy::{
     compiler2,
-    eval::{self, EvalStacktrace},
-    parser::parser, //trace_ctx, Ctx
+    error_codes,
+    eval,
+    parser::{parse_file, Expr}, //trace_ctx, Ctx
+    source_map::SourceMap,
+    trace::{Observer, Silent, StderrTracer},
 };
 
+/// Without a subcommand the arguments are those of `eval`, so that a
+/// script starting with `#!/usr/bin/env frospy` runs.
 #[derive(ClapParser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
     #[command(subcommand)]
-    command: Command,
+    command: Option<Command>,
+    #[command(flatten)]
+    eval: EvalArgs,
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
-    Eval,
-    Compile,
+    Eval(EvalArgs),
+    Compile {
+        /// Source files to compile in order, or stdin if none are given
+        paths: Vec<PathBuf>,
+        #[command(flatten)]
+        limits: Limits,
+    },
+    /// Describe an error code, like F0201, with an example
+    Explain {
+        code: String,
+    },
 }
 
-fn eval() {
-    let src = io::read_to_string(io::stdin()).expect("reading stdin");
+#[derive(Args, Debug)]
+struct EvalArgs {
+    /// Source files to run in order, or stdin if none are given
+    paths: Vec<PathBuf>,
+    #[command(flatten)]
+    limits: Limits,
+    /// Print every step of the evaluation to stderr
+    #[arg(long)]
+    trace: bool,
+}
 
-    println!("{:?}", src);
+/// What the program may use, unbounded unless given
+#[derive(Args, Debug)]
+struct Limits {
+    /// Steps to run for before stopping
+    #[arg(long)]
+    fuel: Option<usize>,
+    /// Values on the stack
+    #[arg(long)]
+    max_stack: Option<usize>,
+    /// Thunks being forced and literals being built at once
+    #[arg(long)]
+    max_frames: Option<usize>,
+    /// Bindings in one environment, besides the builtins
+    #[arg(long)]
+    max_env: Option<usize>,
+}
 
-    let (v, errs) = parser().parse_recovery_verbose(src.clone());
+impl From<Limits> for eval::EvalLimits {
+    fn from(l: Limits) -> Self {
+        eval::EvalLimits {
+            fuel: l.fuel,
+            max_stack: l.max_stack,
+            max_frames: l.max_frames,
+            max_env: l.max_env,
+        }
+    }
+}
 
-    errs.into_iter().for_each(|e| println!("{:#?}", e));
+/// Reads every file into a `SourceMap` and parses them one after the
+/// other, as if they were a single program. Reports every parse error in
+/// every file before exiting if there were any, as it does if a file
+/// can't be read.
+fn load(paths: Vec<PathBuf>) -> (SourceMap, Vec<Expr>) {
+    let mut sources = SourceMap::new();
+    let read = |name: &str, src: io::Result<String>| {
+        src.unwrap_or_else(|e| {
+            eprintln!("error: reading {name}: {e}");
+            process::exit(1);
+        })
+    };
+    if paths.is_empty() {
+        sources.add("<stdin>", read("<stdin>", io::read_to_string(io::stdin())));
+    }
+    for p in paths.iter() {
+        let name = p.display().to_string();
+        let src = read(&name, fs::read_to_string(p));
+        sources.add(name, src);
+    }
 
-    if let Some(ast) = v {
-        println!("{:?}", ast);
+    let mut ast = vec![];
+    let mut failed = false;
+    for file in sources.files() {
+        let src = &sources.get(file).unwrap().src;
+        let (v, diagnostics) = parse_file(file, src);
 
-        match eval::eval(&ast) {
-            Ok(s) => {
-                for (i, v) in s.iter().enumerate() {
-                    println!("s {}: {:}", i, v);
-                }
-            }
-            Err(EvalStacktrace { stack, error }) => {
-                println!("Error: {:?}", error);
-                for (idx, span) in stack.iter().enumerate() {
-                    println!("{} - {:?} \t {}", idx, span.clone(), &src[span.clone()]);
-                }
-            }
+        for d in diagnostics.iter() {
+            eprint!("{}", d.render(&sources));
+            failed = true;
         }
 
-        // let mut ctx = Ctx::new(ast);
+        ast.extend(v);
+    }
+    if failed {
+        process::exit(1);
+    }
+    (sources, ast)
+}
 
-        // trace_ctx(&ctx);
+fn eval(args: EvalArgs) {
+    let (sources, ast) = load(args.paths);
+    let limits = args.limits.into();
+
+    // The first Ctrl-C stops the program with a stacktrace, a second one
+    // in case it doesn't get that far
+    let cancel = eval::CancelToken::new();
+    let handler = cancel.clone();
+    ctrlc::set_handler(move || {
+        if handler.is_cancelled() {
+            process::exit(130);
+        }
+        handler.cancel();
+    })
+    .expect("setting the Ctrl-C handler");
 
-        // while !ctx.pump() {
-        //     trace_ctx(&ctx);
-        // }
+    let mut observer: Box<dyn Observer> = if args.trace {
+        Box::new(StderrTracer::new(&sources))
+    } else {
+        Box::new(Silent)
+    };
+    match eval::eval_with(&ast, eval::EvalOptions { limits, cancel }, &mut *observer) {
+        Ok(s) => {
+            for (i, v) in s.iter().enumerate() {
+                println!("s {}: {:}", i, v);
+            }
+        }
+        Err(e) => {
+            eprint!("{}", e.render(&sources));
+            process::exit(1);
+        }
     }
-}
 
-fn compile() {
-    let src = io::read_to_string(io::stdin()).expect("reading stdin");
+    // let mut ctx = Ctx::new(ast);
 
-    let (v, errs) = parser().parse_recovery_verbose(src.clone());
+    // trace_ctx(&ctx);
 
-    errs.into_iter().for_each(|e| println!("{:#?}", e));
+    // while !ctx.pump() {
+    //     trace_ctx(&ctx);
+    // }
+}
 
-    if let Some(ast) = v {
-        println!(
-            "{}",
-            compiler2::compile(
-                &ast,
-                &compiler2::CompilerOptions {
-                    debug: true,
-                    tracing_exec: true,
-                    ..Default::default()
-                }
-            )
-        );
-    } else {
-        panic!();
+fn compile(paths: Vec<PathBuf>, limits: eval::EvalLimits) {
+    let (sources, ast) = load(paths);
+
+    let code = compiler2::compile(
+        &ast,
+        &sources,
+        &compiler2::CompilerOptions {
+            debug: true,
+            tracing_exec: true,
+            limits,
+            ..Default::default()
+        },
+    );
+    match code {
+        Ok(code) => println!("{}", code),
+        Err(e) => {
+            eprint!("{}", e.render(&sources));
+            process::exit(1);
+        }
+    }
+}
+
+fn explain(code: &str) {
+    let explanation = code
+        .parse()
+        .and_then(|c| error_codes::explain(c).ok_or(format!("no error has code {c}")));
+    match explanation {
+        Ok(e) => print!("{}", e),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
     }
 }
 
 fn main() {
     let cli = Cli::parse();
     match cli.command {
-        Command::Eval => eval(),
-        Command::Compile => compile(),
+        None => eval(cli.eval),
+        Some(Command::Eval(args)) => eval(args),
+        Some(Command::Compile { paths, limits }) => compile(paths, limits.into()),
+        Some(Command::Explain { code }) => explain(&code),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let paths = |args: &[&str]| match Cli::try_parse_from(args).unwrap() {
+            Cli {
+                command: None,
+                eval,
+            }
+            | Cli {
+                command: Some(Command::Eval(eval)),
+                ..
+            } => eval.paths,
+            cli => panic!("not eval: {cli:?}"),
+        };
+        // As run by `#!/usr/bin/env frospy` and `#!/usr/bin/env -S frospy eval`
+        assert_eq!(paths(&["frospy", "a.fpy"]), [PathBuf::from("a.fpy")]);
+        assert_eq!(
+            paths(&["frospy", "eval", "a.fpy"]),
+            [PathBuf::from("a.fpy")]
+        );
+        assert_eq!(
+            paths(&["frospy", "--fuel", "5", "a.fpy"]),
+            [PathBuf::from("a.fpy")]
+        );
+        assert!(paths(&["frospy"]).is_empty());
+
+        assert!(matches!(
+            Cli::try_parse_from(["frospy", "compile", "a.fpy"])
+                .unwrap()
+                .command,
+            Some(Command::Compile { .. })
+        ));
     }
 }
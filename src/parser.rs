@@ -3,6 +3,13 @@ use std::ops::Range;
 
 use chumsky::prelude::*;
 use chumsky::Parser;
+use thiserror::Error;
+
+use crate::cst;
+use crate::delimiters;
+use crate::diagnostic::Diagnostic;
+use crate::error_codes::{self, ErrorCode};
+use crate::reader;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AtomMod {
@@ -11,43 +18,136 @@ pub enum AtomMod {
     QuotePush,
 }
 
-pub type Span = Range<usize>;
+/// The atoms a sigil stands for: `'x` is `quote x`, `$x` is `quote x pop`
+/// and `^x` is `quote x push`. They all share the span of the original.
+pub fn expand_atom(m: Option<AtomMod>, s: &str, span: &Span) -> Vec<Expr> {
+    match m {
+        Some(AtomMod::Quote) => vec!["quote", s],
+        Some(AtomMod::QuotePop) => vec!["quote", s, "pop"],
+        Some(AtomMod::QuotePush) => vec!["quote", s, "push"],
+        None => vec![s],
+    }
+    .into_iter()
+    .map(|a| Expr::Atom(a.to_string(), span.clone()))
+    .collect()
+}
+
+/// Identifies a source file registered with a `SourceMap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileId(pub usize);
+
+/// A range of char offsets into one source file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub file: FileId,
+    pub range: Range<usize>,
+}
+
+impl Span {
+    pub fn new(file: FileId, range: Range<usize>) -> Self {
+        Span { file, range }
+    }
+
+    pub fn start(&self) -> usize {
+        self.range.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.range.end
+    }
+}
 
 pub fn span_combine(s1: &Span, s2: &Span) -> Span {
-    s1.start..s2.end
+    Span::new(s1.file, s1.start()..s2.end())
 }
 
 pub fn dummy_span() -> Span {
-    0..0
+    Span::default()
 }
 
 pub fn span_start_span(s: &Span) -> Span {
-    s.start..s.start
+    Span::new(s.file, s.start()..s.start())
 }
 pub fn span_end_span(s: &Span) -> Span {
-    s.end..s.end
+    Span::new(s.file, s.end()..s.end())
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     Integer(i64, Span),
+    Float(f64, Span),
     Atom(String, Span),
+    String(String, Span),
+    Char(char, Span),
+    Bytes(Vec<u8>, Span),
     Thunk(Vec<Self>, Span),
+    List(Vec<Self>, Span),
+    Map(Vec<Self>, Span),
+    /// Only ever the first expression of a file
+    Pragma(Pragma, Span),
 }
 
+/// Doesn't recurse, so deeply nested expressions print too.
 impl Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Expr::Integer(i, _) => f.write_fmt(format_args!("{}", i)),
-            Expr::Atom(a, _) => f.write_fmt(format_args!("{}", a)),
-            Expr::Thunk(es, _) => {
-                f.write_str("( ")?;
-                for e in es.iter() {
-                    f.write_fmt(format_args!("{} ", e))?;
+        enum Todo<'a> {
+            Expr(&'a Expr),
+            Str(&'static str),
+        }
+
+        let mut todo = vec![Todo::Expr(self)];
+        while let Some(t) = todo.pop() {
+            let e = match t {
+                Todo::Expr(e) => e,
+                Todo::Str(s) => {
+                    f.write_str(s)?;
+                    continue;
+                }
+            };
+            let (open, es, close) = match e {
+                Expr::Integer(i, _) => {
+                    f.write_fmt(format_args!("{}", i))?;
+                    continue;
+                }
+                Expr::Float(x, _) => {
+                    f.write_str(&float_literal(*x))?;
+                    continue;
                 }
-                f.write_str(")")
+                Expr::Atom(a, _) => {
+                    f.write_fmt(format_args!("{}", a))?;
+                    continue;
+                }
+                Expr::String(s, _) => {
+                    f.write_fmt(format_args!("{:?}", s))?;
+                    continue;
+                }
+                Expr::Char(c, _) => {
+                    f.write_str(&char_literal(*c))?;
+                    continue;
+                }
+                Expr::Bytes(b, _) => {
+                    f.write_str(&bytes_literal(b))?;
+                    continue;
+                }
+                Expr::Pragma(p, _) => {
+                    f.write_fmt(format_args!("{}", p))?;
+                    continue;
+                }
+                Expr::Thunk(es, _) => ("( ", es, ")"),
+                Expr::List(es, _) => ("[ ", es, "]"),
+                Expr::Map(es, _) => ("{ ", es, "}"),
+            };
+            f.write_str(open)?;
+            todo.push(Todo::Str(close));
+            for e in es.iter().rev() {
+                todo.push(Todo::Str(" "));
+                todo.push(Todo::Expr(e));
             }
         }
+        Ok(())
     }
 }
 
@@ -55,100 +155,888 @@ impl Expr {
     pub fn get_span(&self) -> &Span {
         match self {
             Expr::Integer(_, s) => s,
+            Expr::Float(_, s) => s,
             Expr::Atom(_, s) => s,
+            Expr::String(_, s) => s,
+            Expr::Char(_, s) => s,
+            Expr::Bytes(_, s) => s,
             Expr::Thunk(_, s) => s,
+            Expr::List(_, s) => s,
+            Expr::Map(_, s) => s,
+            Expr::Pragma(_, s) => s,
+        }
+    }
+
+    /// The expressions nested in a thunk, list or map.
+    pub fn children(&self) -> Option<&[Expr]> {
+        match self {
+            Expr::Thunk(es, _) | Expr::List(es, _) | Expr::Map(es, _) => Some(es),
+            _ => None,
+        }
+    }
+
+    fn children_mut(&mut self) -> Option<&mut Vec<Expr>> {
+        match self {
+            Expr::Thunk(es, _) | Expr::List(es, _) | Expr::Map(es, _) => Some(es),
+            _ => None,
+        }
+    }
+
+    /// Replaces every span in this and any nested expressions. Doesn't
+    /// recurse, so it works however deeply `reader` nested them.
+    pub fn map_spans(&mut self, f: &impl Fn(&Span) -> Span) {
+        let mut todo = vec![self];
+        while let Some(e) = todo.pop() {
+            match e {
+                Expr::Thunk(es, s) | Expr::List(es, s) | Expr::Map(es, s) => {
+                    *s = f(s);
+                    todo.extend(es.iter_mut());
+                }
+                Expr::Integer(_, s)
+                | Expr::Float(_, s)
+                | Expr::Atom(_, s)
+                | Expr::String(_, s)
+                | Expr::Char(_, s)
+                | Expr::Bytes(_, s)
+                | Expr::Pragma(_, s) => *s = f(s),
+            }
+        }
+    }
+}
+
+/// Like `map_spans`, cloning and dropping don't recurse, so neither
+/// overflows the stack on deeply nested expressions.
+impl Clone for Expr {
+    fn clone(&self) -> Self {
+        enum Todo<'a> {
+            Clone(&'a Expr),
+            /// Takes its clone's children off the end of `done`
+            Assemble(&'a Expr),
+        }
+
+        let mut todo = vec![Todo::Clone(self)];
+        let mut done: Vec<Expr> = vec![];
+        while let Some(t) = todo.pop() {
+            match t {
+                Todo::Clone(e) => match e.children() {
+                    Some(es) => {
+                        todo.push(Todo::Assemble(e));
+                        todo.extend(es.iter().rev().map(Todo::Clone));
+                    }
+                    None => done.push(match e {
+                        Expr::Integer(i, s) => Expr::Integer(*i, s.clone()),
+                        Expr::Float(x, s) => Expr::Float(*x, s.clone()),
+                        Expr::Atom(a, s) => Expr::Atom(a.clone(), s.clone()),
+                        Expr::String(st, s) => Expr::String(st.clone(), s.clone()),
+                        Expr::Char(c, s) => Expr::Char(*c, s.clone()),
+                        Expr::Bytes(b, s) => Expr::Bytes(b.clone(), s.clone()),
+                        Expr::Pragma(p, s) => Expr::Pragma(p.clone(), s.clone()),
+                        Expr::Thunk(..) | Expr::List(..) | Expr::Map(..) => unreachable!(),
+                    }),
+                },
+                Todo::Assemble(e) => {
+                    let n = e.children().map_or(0, <[Expr]>::len);
+                    let es = done.split_off(done.len() - n);
+                    done.push(match e {
+                        Expr::Thunk(_, s) => Expr::Thunk(es, s.clone()),
+                        Expr::List(_, s) => Expr::List(es, s.clone()),
+                        Expr::Map(_, s) => Expr::Map(es, s.clone()),
+                        _ => unreachable!(),
+                    });
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut todo = match self.children_mut() {
+            Some(es) => std::mem::take(es),
+            None => return,
+        };
+        while let Some(mut e) = todo.pop() {
+            if let Some(es) = e.children_mut() {
+                todo.append(es);
+            }
+        }
+    }
+}
+
+/// The language version this implementation understands.
+pub const LANGUAGE_VERSION: u32 = 1;
+
+/// Extensions a pragma can turn on. There aren't any yet, but naming one
+/// is an error rather than silently running without it.
+pub const EXTENSIONS: &[&str] = &[];
+
+/// `#frospy <version> <extension>...`, on its own line at the top of a
+/// source file, after any shebang.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pragma {
+    pub version: u32,
+    pub extensions: Vec<String>,
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PragmaError {
+    #[error("Unsupported language version {0}, only version {LANGUAGE_VERSION} is supported")]
+    UnsupportedVersion(u32),
+
+    #[error("Unknown language extension {0}")]
+    UnknownExtension(String),
+}
+
+impl PragmaError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            PragmaError::UnsupportedVersion(_) => error_codes::UNSUPPORTED_VERSION,
+            PragmaError::UnknownExtension(_) => error_codes::UNKNOWN_EXTENSION,
+        }
+    }
+}
+
+impl Pragma {
+    /// Whether this implementation can run a program with this pragma.
+    pub fn check(&self) -> Result<(), PragmaError> {
+        if self.version != LANGUAGE_VERSION {
+            return Err(PragmaError::UnsupportedVersion(self.version));
+        }
+        match self
+            .extensions
+            .iter()
+            .find(|e| !EXTENSIONS.contains(&e.as_str()))
+        {
+            Some(e) => Err(PragmaError::UnknownExtension(e.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Display for Pragma {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("#frospy {}", self.version))?;
+        for e in self.extensions.iter() {
+            f.write_fmt(format_args!(" {}", e))?;
         }
+        Ok(())
     }
 }
 
-fn atom_parser() -> impl Parser<char, Vec<Expr>, Error = Simple<char>> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommentKind {
+    /// `; ...` up to the end of the line
+    Line,
+    /// `#| ... |#`, which may nest
+    Block,
+}
+
+/// A comment in the source. The parser treats comments as whitespace, so
+/// they never appear in `Expr`, but `comments` can recover them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub kind: CommentKind,
+    pub text: String,
+    pub span: Span,
+}
+
+pub(crate) fn comment_parser() -> impl Parser<char, Comment, Error = Simple<char>> + Clone {
+    let line = just(';')
+        .ignore_then(filter(|c: &char| *c != '\n').repeated())
+        .collect::<String>()
+        .map_with_span(|text, span| Comment {
+            kind: CommentKind::Line,
+            text,
+            span: Span::new(FileId::default(), span),
+        });
+
+    let block = recursive(|block| {
+        just("#|")
+            .ignore_then(
+                choice((
+                    block.map(|inner: String| format!("#|{inner}|#")),
+                    just("|#").not().map(|c: char| c.to_string()),
+                ))
+                .repeated(),
+            )
+            .then_ignore(just("|#"))
+            .map(|parts: Vec<String>| parts.concat())
+    })
+    .map_with_span(|text, span| Comment {
+        kind: CommentKind::Block,
+        text,
+        span: Span::new(FileId::default(), span),
+    });
+
+    choice((line, block)).labelled("comment")
+}
+
+/// Every comment in `file`, in order, for tools that need to keep them.
+pub fn comments(file: FileId) -> impl Parser<char, Vec<Comment>, Error = Simple<char>> {
     choice((
-        just('\'').to(AtomMod::Quote),
-        just('$').to(AtomMod::QuotePop),
-        just('^').to(AtomMod::QuotePush),
+        comment_parser().map(Some),
+        string_literal().to(None),
+        bytes_literal_parser().to(None),
+        just("#\\").then(any()).to(None),
+        any().to(None),
     ))
-    .or_not()
-    .then(text::ident())
-    .labelled("atom")
-    .map_with_span(|(m, s), span: Span| -> Vec<Expr> {
-        match m {
-            Some(AtomMod::Quote) => vec!["quote".to_string(), s],
-            Some(AtomMod::QuotePop) => vec!["quote".to_string(), s, "pop".to_string()],
-            Some(AtomMod::QuotePush) => vec!["quote".to_string(), s, "push".to_string()],
-            None => vec![s],
-        }
-        .into_iter()
-        .map(|a| Expr::Atom(a, span.clone()))
-        .collect::<Vec<Expr>>()
+    .repeated()
+    .then_ignore(end())
+    .map(move |cs| {
+        cs.into_iter()
+            .flatten()
+            .map(|c| Comment {
+                span: Span::new(file, c.span.range),
+                ..c
+            })
+            .collect()
     })
-    .padded()
 }
 
-pub fn parser() -> impl Parser<char, Vec<Expr>, Error = Simple<char>> {
-    let integer = text::int(10)
-        .from_str()
-        .unwrapped()
-        .labelled("integer")
-        .map_with_span(|i, span| vec![Expr::Integer(i, span)])
-        .padded();
+/// Characters that end an atom. Everything else that isn't whitespace can
+/// appear in one, so `+`, `<=`, `2dup` and `empty?` are all atoms.
+pub(crate) fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{}\";".contains(c)
+}
+
+/// Characters that can't start an atom, either because they are a sigil or
+/// because they introduce other syntax.
+pub(crate) fn is_atom_start(c: char) -> bool {
+    !is_delimiter(c) && !"'$^#".contains(c)
+}
+
+/// Reads an integer literal: an optional sign, then decimal digits or
+/// digits after a `0x`, `0o` or `0b` radix prefix. None means the word isn't
+/// an integer at all and so is an atom, like `-`, `2dup` or `0xzz`.
+pub(crate) fn parse_integer(w: &str) -> Option<Result<i64, String>> {
+    let (sign, rest) = match w.strip_prefix(['-', '+']) {
+        Some(rest) => (&w[..1], rest),
+        None => ("", w),
+    };
+
+    let (radix, digits) = match rest.get(..2) {
+        Some("0x") => (16, &rest[2..]),
+        Some("0o") => (8, &rest[2..]),
+        Some("0b") => (2, &rest[2..]),
+        _ => (10, rest),
+    };
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+
+    Some(
+        i64::from_str_radix(&format!("{sign}{digits}"), radix)
+            .map_err(|_| format!("integer literal {w} doesn't fit in 64 bits")),
+    )
+}
+
+/// Reads a float literal, which needs a fraction, an exponent or both so
+/// that it can't be confused with an integer.
+pub(crate) fn parse_float(w: &str) -> Option<Result<f64, String>> {
+    fn unsigned(s: &str) -> &str {
+        s.strip_prefix(['-', '+']).unwrap_or(s)
+    }
+    let all_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+
+    let (mantissa, exponent) = match unsigned(w).split_once(['e', 'E']) {
+        Some((m, e)) => (m, Some(unsigned(e))),
+        None => (unsigned(w), None),
+    };
+    let (int, frac) = match mantissa.split_once('.') {
+        Some((i, f)) => (i, Some(f)),
+        None => (mantissa, None),
+    };
+
+    if !all_digits(int)
+        || !frac.is_none_or(all_digits)
+        || !exponent.is_none_or(all_digits)
+        || (frac.is_none() && exponent.is_none())
+    {
+        return None;
+    }
+
+    Some(
+        w.parse::<f64>()
+            .ok()
+            .filter(|f| f.is_finite())
+            .ok_or_else(|| format!("float literal {w} is out of range")),
+    )
+}
+
+/// The literal syntax for a float, which parses back to the same value.
+/// Non-finite values are spelled `#inf`, `#-inf` and `#nan` so they don't
+/// collide with atoms.
+pub fn float_literal(f: f64) -> String {
+    if f.is_nan() {
+        "#nan".to_string()
+    } else if f.is_infinite() {
+        if f > 0.0 { "#inf" } else { "#-inf" }.to_string()
+    } else {
+        format!("{:?}", f)
+    }
+}
+
+/// Names for characters that can't be written after `#\` directly.
+const CHAR_NAMES: [(&str, char); 5] = [
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("nul", '\0'),
+];
+
+/// Reads what follows the `#\` of a char literal: a single character, one
+/// of the names in `CHAR_NAMES`, or `x` and a hex code point.
+pub(crate) fn parse_char(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(c);
+    }
+
+    if let Some((_, c)) = CHAR_NAMES.iter().find(|(n, _)| *n == s) {
+        return Ok(*c);
+    }
+
+    s.strip_prefix('x')
+        .and_then(|h| u32::from_str_radix(h, 16).ok())
+        .and_then(char::from_u32)
+        .ok_or_else(|| format!("unknown character #\\{s}"))
+}
+
+/// The literal syntax for a char, which parses back to the same value.
+pub fn char_literal(c: char) -> String {
+    if let Some((n, _)) = CHAR_NAMES.iter().find(|(_, nc)| *nc == c) {
+        format!("#\\{n}")
+    } else if c.is_control() || c.is_whitespace() {
+        format!("#\\x{:x}", c as u32)
+    } else {
+        format!("#\\{c}")
+    }
+}
+
+/// The literal syntax for a byte string, escaping anything that isn't
+/// printable ASCII.
+pub fn bytes_literal(b: &[u8]) -> String {
+    let mut s = "b\"".to_string();
+    for byte in b.iter() {
+        match byte {
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            b'\n' => s.push_str("\\n"),
+            b'\r' => s.push_str("\\r"),
+            b'\t' => s.push_str("\\t"),
+            b' '..=b'~' => s.push(*byte as char),
+            _ => s.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+    s.push('"');
+    s
+}
 
-    let mut expr = Recursive::declare();
+pub(crate) fn word() -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    filter(|c: &char| is_atom_start(*c))
+        .chain(filter(|c: &char| !is_delimiter(*c)).repeated())
+        .collect()
+}
 
-    let thunk = expr
-        .clone()
+pub(crate) fn string_literal() -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    let unicode = filter(|c: &char| c.is_ascii_hexdigit())
         .repeated()
-        .flatten()
-        // This padding is semantically necessary, but screws up the span
-        .delimited_by(just('(').padded(), just(')').padded())
-        .map_with_span(|elements, span| vec![Expr::Thunk(elements, span)])
-        .labelled("thunk");
+        .at_least(1)
+        .at_most(6)
+        .collect::<String>()
+        .delimited_by(just('{'), just('}'))
+        .try_map(|s, span| {
+            u32::from_str_radix(&s, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| Simple::custom(span, "invalid unicode escape"))
+        });
+
+    let escape = just('\\').ignore_then(choice((
+        just('\\'),
+        just('"'),
+        just('n').to('\n'),
+        just('r').to('\r'),
+        just('t').to('\t'),
+        just('0').to('\0'),
+        just('u').ignore_then(unicode),
+    )));
+
+    just('"')
+        .ignore_then(
+            filter(|c: &char| *c != '\\' && *c != '"')
+                .or(escape)
+                .repeated(),
+        )
+        .then_ignore(just('"'))
+        .collect::<String>()
+}
 
-    expr.define(choice((atom_parser(), integer, thunk)).labelled("expr"));
+/// `b"..."`, which only takes ASCII directly but can have any byte as a
+/// `\xHH` escape.
+pub(crate) fn bytes_literal_parser() -> impl Parser<char, Vec<u8>, Error = Simple<char>> + Clone {
+    let hex = filter(|c: &char| c.is_ascii_hexdigit())
+        .repeated()
+        .exactly(2)
+        .collect::<String>()
+        .map(|s| u8::from_str_radix(&s, 16).unwrap());
+
+    let escape = just('\\').ignore_then(choice((
+        just('\\').to(b'\\'),
+        just('"').to(b'"'),
+        just('n').to(b'\n'),
+        just('r').to(b'\r'),
+        just('t').to(b'\t'),
+        just('0').to(b'\0'),
+        just('x').ignore_then(hex),
+    )));
+
+    // Validated rather than rejected, so that b"λ" doesn't fall back to
+    // being the atom b and a string
+    let byte = filter(|c: &char| *c != '\\' && *c != '"').validate(|c: char, span, emit| {
+        if !c.is_ascii() {
+            emit(Simple::custom(span, "non-ASCII character in byte string"))
+        }
+        c as u8
+    });
 
-    expr.repeated().flatten().then_ignore(end())
+    just("b\"")
+        .ignore_then(byte.or(escape).repeated())
+        .then_ignore(just('"'))
+}
+
+/// Parses a program that isn't registered with a `SourceMap`, so its
+/// spans all point into the first file.
+pub fn parser() -> impl Parser<char, Vec<Expr>, Error = Simple<char>> {
+    file_parser(FileId::default())
+}
+
+/// Parses the source of `file`, tagging every span with it.
+pub fn file_parser(file: FileId) -> impl Parser<char, Vec<Expr>, Error = Simple<char>> {
+    cst::file_parser(file).map(|cst| cst.lower())
+}
+
+/// Reads the source of `file` with `reader`, so deep nesting and large
+/// files are fine, and reports every error in it, not just the first.
+/// Delimiters are balanced beforehand, so a missing `)` is reported where
+/// it was probably meant to go and doesn't hide the errors after it.
+pub fn parse_file(file: FileId, src: &str) -> (Vec<Expr>, Vec<Diagnostic>) {
+    let repair = delimiters::balance(file, src);
+    let (mut exprs, errs) = reader::read_recovery(file, &repair.src);
+
+    let mut diagnostics = repair.diagnostics.clone();
+    diagnostics.extend(errs.iter().map(|e| {
+        let mut d = Diagnostic::from_parse_error(file, e);
+        d.span = repair.original_span(&d.span);
+        d
+    }));
+    diagnostics.sort_by_key(|d| d.span.start());
+
+    for e in exprs.iter_mut() {
+        e.map_spans(&|s| repair.original_span(s));
+    }
+    (exprs, diagnostics)
 }
 
 #[cfg(test)]
 mod test_parser {
     use super::*;
 
+    fn sp(range: Range<usize>) -> Span {
+        Span::new(FileId::default(), range)
+    }
+
+    fn atom_parser(file: FileId) -> impl Parser<char, Vec<Expr>, Error = Simple<char>> {
+        cst::node(file, cst::atom())
+            .then_ignore(cst::trivia(file))
+            .map(|n| n.lower())
+    }
+
+    fn string_parser(file: FileId) -> impl Parser<char, Vec<Expr>, Error = Simple<char>> {
+        cst::node(file, cst::string())
+            .then_ignore(cst::trivia(file))
+            .map(|n| n.lower())
+    }
+
     #[test]
     fn test_atom_parser() {
         assert_eq!(
-            atom_parser().parse("test"),
-            Ok(vec![Expr::Atom("test".to_string(), 0..4)])
+            atom_parser(FileId::default()).parse("test"),
+            Ok(vec![Expr::Atom("test".to_string(), sp(0..4))])
         );
         assert_eq!(
-            atom_parser().parse("   test   "),
-            Ok(vec![Expr::Atom("test".to_string(), 3..7)])
+            atom_parser(FileId::default()).parse("   test   "),
+            Ok(vec![Expr::Atom("test".to_string(), sp(3..7))])
         );
 
         assert_eq!(
-            atom_parser().parse("   t123   "),
-            Ok(vec![Expr::Atom("t123".to_string(), 3..7)])
+            atom_parser(FileId::default()).parse("   t123   "),
+            Ok(vec![Expr::Atom("t123".to_string(), sp(3..7))])
         );
 
         assert_eq!(
-            atom_parser().parse("   'test   "),
+            atom_parser(FileId::default()).parse("   'test   "),
             Ok(vec![
-                Expr::Atom("quote".to_string(), 3..8),
-                Expr::Atom("test".to_string(), 3..8),
+                Expr::Atom("quote".to_string(), sp(3..8)),
+                Expr::Atom("test".to_string(), sp(3..8)),
             ])
         );
         assert_eq!(
-            atom_parser().parse("   $test   "),
+            atom_parser(FileId::default()).parse("   $test   "),
             Ok(vec![
-                Expr::Atom("quote".to_string(), 3..8),
-                Expr::Atom("test".to_string(), 3..8),
-                Expr::Atom("pop".to_string(), 3..8),
+                Expr::Atom("quote".to_string(), sp(3..8)),
+                Expr::Atom("test".to_string(), sp(3..8)),
+                Expr::Atom("pop".to_string(), sp(3..8)),
             ])
         );
         assert_eq!(
-            atom_parser().parse("   ^test   "),
+            atom_parser(FileId::default()).parse("   ^test   "),
             Ok(vec![
-                Expr::Atom("quote".to_string(), 3..8),
-                Expr::Atom("test".to_string(), 3..8),
-                Expr::Atom("push".to_string(), 3..8),
+                Expr::Atom("quote".to_string(), sp(3..8)),
+                Expr::Atom("test".to_string(), sp(3..8)),
+                Expr::Atom("push".to_string(), sp(3..8)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_operator_atoms() {
+        let atoms = |src: &str| -> Vec<String> {
+            parser()
+                .parse(src)
+                .unwrap()
+                .into_iter()
+                .map(|e| match &e {
+                    Expr::Atom(a, _) => a.clone(),
+                    e => panic!("expected atom, got {e:?}"),
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            atoms("+ - * / = < > <= 2dup empty? a->b x'"),
+            vec!["+", "-", "*", "/", "=", "<", ">", "<=", "2dup", "empty?", "a->b", "x'"]
+        );
+        assert_eq!(atoms("'<= $+"), vec!["quote", "<=", "quote", "+", "pop"]);
+
+        assert_eq!(
+            parser().parse("(+)12 3"),
+            Ok(vec![
+                Expr::Thunk(vec![Expr::Atom("+".to_string(), sp(1..2))], sp(0..3)),
+                Expr::Integer(12, sp(3..5)),
+                Expr::Integer(3, sp(6..7)),
+            ])
+        );
+        assert_eq!(parser().parse("007"), Ok(vec![Expr::Integer(7, sp(0..3))]));
+        assert!(parser().parse("'12").is_err());
+        assert!(parser().parse("#foo").is_err());
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        let ints = |src: &str| -> Vec<i64> {
+            parser()
+                .parse(src)
+                .unwrap()
+                .into_iter()
+                .map(|e| match e {
+                    Expr::Integer(i, _) => i,
+                    e => panic!("expected integer, got {e:?}"),
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            ints("-5 +5 0xff -0x10 0o17 0b101 -0b1"),
+            vec![-5, 5, 255, -16, 15, 5, -1]
+        );
+        assert_eq!(
+            ints("9223372036854775807 -9223372036854775808"),
+            vec![i64::MAX, i64::MIN]
+        );
+        assert_eq!(
+            parser().parse("- -x 0x 0xzz 5-"),
+            Ok(vec![
+                Expr::Atom("-".to_string(), sp(0..1)),
+                Expr::Atom("-x".to_string(), sp(2..4)),
+                Expr::Atom("0x".to_string(), sp(5..7)),
+                Expr::Atom("0xzz".to_string(), sp(8..12)),
+                Expr::Atom("5-".to_string(), sp(13..15)),
+            ])
+        );
+
+        let errs = parser().parse("1 (99999999999999999999)").unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].span(), 3..23);
+        assert_eq!(
+            errs[0].reason(),
+            &chumsky::error::SimpleReason::Custom(
+                "integer literal 99999999999999999999 doesn't fit in 64 bits".to_string()
+            )
+        );
+        assert!(parser().parse("0x8000000000000000").is_err());
+    }
+
+    #[test]
+    fn test_float_literals() {
+        let floats = |src: &str| -> Vec<f64> {
+            parser()
+                .parse(src)
+                .unwrap()
+                .into_iter()
+                .map(|e| match e {
+                    Expr::Float(f, _) => f,
+                    e => panic!("expected float, got {e:?}"),
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            floats("1.5 -0.25 +2.0 1e3 1E-2 -6.02e23 #inf #-inf"),
+            vec![
+                1.5,
+                -0.25,
+                2.0,
+                1000.0,
+                0.01,
+                -6.02e23,
+                f64::INFINITY,
+                f64::NEG_INFINITY
+            ]
+        );
+        assert!(floats("#nan")[0].is_nan());
+
+        assert_eq!(
+            parser().parse("1. .5 1e 1.5x"),
+            Ok(vec![
+                Expr::Atom("1.".to_string(), sp(0..2)),
+                Expr::Atom(".5".to_string(), sp(3..5)),
+                Expr::Atom("1e".to_string(), sp(6..8)),
+                Expr::Atom("1.5x".to_string(), sp(9..13)),
+            ])
+        );
+        assert!(parser().parse("1e400").is_err());
+        assert!(parser().parse("#infinity").is_err());
+
+        for f in [
+            0.1,
+            -0.0,
+            1.0,
+            1e100,
+            1.5e-7,
+            123456789.125,
+            f64::MAX,
+            f64::INFINITY,
+        ] {
+            assert_eq!(floats(&float_literal(f)), vec![f]);
+        }
+    }
+
+    #[test]
+    fn test_string_parser() {
+        assert_eq!(
+            string_parser(FileId::default()).parse(r#"  "hello world"  "#),
+            Ok(vec![Expr::String("hello world".to_string(), sp(2..15))])
+        );
+        assert_eq!(
+            string_parser(FileId::default()).parse(r#""a\"b\\c\n\t\u{3bb}""#),
+            Ok(vec![Expr::String(
+                "a\"b\\c\n\t\u{3bb}".to_string(),
+                sp(0..20)
+            )])
+        );
+        assert!(string_parser(FileId::default())
+            .parse(r#""unterminated"#)
+            .is_err());
+        assert!(string_parser(FileId::default()).parse(r#""\q""#).is_err());
+        assert!(string_parser(FileId::default())
+            .parse(r#""\u{110000}""#)
+            .is_err());
+    }
+
+    #[test]
+    fn test_char_and_bytes_literals() {
+        assert_eq!(
+            parser().parse(r"#\a #\( #\space #\x3bb #\λ"),
+            Ok(vec![
+                Expr::Char('a', sp(0..3)),
+                Expr::Char('(', sp(4..7)),
+                Expr::Char(' ', sp(8..15)),
+                Expr::Char('λ', sp(16..22)),
+                Expr::Char('λ', sp(23..26)),
+            ])
+        );
+        assert!(parser().parse(r"#\bogus").is_err());
+        assert!(parser().parse(r"#\x110000").is_err());
+
+        assert_eq!(
+            parser().parse(r#"b"a\"\x00\xff" b"""#),
+            Ok(vec![
+                Expr::Bytes(vec![b'a', b'"', 0, 255], sp(0..14)),
+                Expr::Bytes(vec![], sp(15..18)),
+            ])
+        );
+        assert!(parser().parse(r#"b"λ""#).is_err());
+        assert!(parser().parse(r#"b"\x1""#).is_err());
+        // Still an atom without the quote
+        assert_eq!(
+            parser().parse("b"),
+            Ok(vec![Expr::Atom("b".to_string(), sp(0..1))])
+        );
+
+        for c in ['a', ' ', '\n', '\u{7f}', '#', ';', 'λ'] {
+            assert_eq!(
+                parser().parse(char_literal(c)),
+                Ok(vec![Expr::Char(c, sp(0..char_literal(c).chars().count()))])
+            );
+        }
+        assert_eq!(
+            comments(FileId::default())
+                .parse(r#"#\; b";" #\"; c"#)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let all: Vec<u8> = (0..=255).collect();
+        let lit = bytes_literal(&all);
+        assert_eq!(
+            parser().parse(lit.clone()),
+            Ok(vec![Expr::Bytes(all, sp(0..lit.len()))])
+        );
+    }
+
+    #[test]
+    fn test_file_spans() {
+        let file = FileId(3);
+        assert_eq!(
+            file_parser(file).parse("a '(b)"),
+            Ok(vec![
+                Expr::Atom("a".to_string(), Span::new(file, 0..1)),
+                Expr::Atom("quote".to_string(), Span::new(file, 2..3)),
+                Expr::Thunk(
+                    vec![Expr::Atom("b".to_string(), Span::new(file, 4..5))],
+                    Span::new(file, 3..6)
+                ),
+            ])
+        );
+        assert_eq!(
+            comments(file).parse("a ; b"),
+            Ok(vec![Comment {
+                kind: CommentKind::Line,
+                text: " b".to_string(),
+                span: Span::new(file, 2..5),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let src = "($x\n  ^x inc\n$f\n(1 99999999999999999999) 2 f";
+        let (exprs, ds) = parse_file(FileId::default(), src);
+        assert_eq!(
+            ds.iter()
+                .map(|d| (d.code.unwrap(), d.span.range.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (error_codes::UNCLOSED_DELIMITER, 0..1),
+                (error_codes::INVALID_LITERAL, 19..39),
+            ]
+        );
+        // Spans after the inserted `)` still point into the original
+        assert_eq!(exprs[0].get_span(), &sp(0..13));
+        assert_eq!(exprs.last(), Some(&Expr::Atom("f".to_string(), sp(43..44))));
+
+        let (exprs, ds) = parse_file(FileId::default(), "1 2 +");
+        assert!(ds.is_empty());
+        assert_eq!(exprs.len(), 3);
+    }
+
+    #[test]
+    fn test_shebang_and_pragma() {
+        assert_eq!(
+            parser().parse("#!/usr/bin/env -S frospy eval\n1"),
+            Ok(vec![Expr::Integer(1, sp(30..31))])
+        );
+        assert!(parser().parse("1\n#!/usr/bin/env frospy").is_err());
+
+        let pragma = |version, extensions: &[&str]| Pragma {
+            version,
+            extensions: extensions.iter().map(|e| e.to_string()).collect(),
+        };
+        assert_eq!(
+            parser().parse("#!frospy\n; about\n#frospy 1 ; comment\n2"),
+            Ok(vec![
+                Expr::Pragma(pragma(1, &[]), sp(17..27)),
+                Expr::Integer(2, sp(37..38)),
+            ])
+        );
+        assert_eq!(
+            parser().parse("#frospy 2 a b"),
+            Ok(vec![Expr::Pragma(pragma(2, &["a", "b"]), sp(0..13))])
+        );
+        assert!(parser().parse("#frospy").is_err());
+        assert!(parser().parse("#frospy one").is_err());
+        assert!(parser().parse("1 #frospy 1").is_err());
+
+        assert_eq!(pragma(1, &[]).check(), Ok(()));
+        assert_eq!(
+            pragma(2, &[]).check(),
+            Err(PragmaError::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            pragma(1, &["macros"]).check(),
+            Err(PragmaError::UnknownExtension("macros".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            parser().parse("; leading\n1 ; trailing"),
+            Ok(vec![Expr::Integer(1, sp(10..11))])
+        );
+        assert_eq!(
+            parser().parse("(a #| inner #| nested |# still |# b)"),
+            Ok(vec![Expr::Thunk(
+                vec![
+                    Expr::Atom("a".to_string(), sp(1..2)),
+                    Expr::Atom("b".to_string(), sp(34..35)),
+                ],
+                sp(0..36)
+            )])
+        );
+        assert_eq!(
+            parser().parse("a;b\nc"),
+            Ok(vec![
+                Expr::Atom("a".to_string(), sp(0..1)),
+                Expr::Atom("c".to_string(), sp(4..5)),
+            ])
+        );
+        assert_eq!(
+            parser().parse(r#""; not a comment""#),
+            Ok(vec![Expr::String("; not a comment".to_string(), sp(0..17))])
+        );
+        assert!(parser().parse("#| unterminated").is_err());
+        assert!(parser().parse("#| a #| b |#").is_err());
+
+        assert_eq!(
+            comments(FileId::default()).parse("1 ; one\n\"; two\" #| three #|four|# |#"),
+            Ok(vec![
+                Comment {
+                    kind: CommentKind::Line,
+                    text: " one".to_string(),
+                    span: sp(2..7),
+                },
+                Comment {
+                    kind: CommentKind::Block,
+                    text: " three #|four|# ".to_string(),
+                    span: sp(16..36),
+                },
             ])
         );
     }
@@ -159,31 +1047,71 @@ mod test_parser {
         assert_eq!(
             parser().parse("$test"),
             Ok(vec![
-                Expr::Atom("quote".to_string(), 0..5),
-                Expr::Atom("test".to_string(), 0..5),
-                Expr::Atom("pop".to_string(), 0..5),
+                Expr::Atom("quote".to_string(), sp(0..5)),
+                Expr::Atom("test".to_string(), sp(0..5)),
+                Expr::Atom("pop".to_string(), sp(0..5)),
             ])
         );
         assert_eq!(
             parser().parse("()\n"),
-            Ok(vec![Expr::Thunk(vec![], 0..3),]) // FIXME
+            Ok(vec![Expr::Thunk(vec![], sp(0..2)),])
         );
         assert_eq!(
             parser().parse("( )\n"),
-            Ok(vec![Expr::Thunk(vec![], 0..4),]) // FIXME
+            Ok(vec![Expr::Thunk(vec![], sp(0..3)),])
         );
         assert_eq!(
             parser().parse(" ( ) \n"),
-            Ok(vec![Expr::Thunk(vec![], 0..6),]) // FIXME
+            Ok(vec![Expr::Thunk(vec![], sp(1..4)),])
         );
         assert_eq!(
             parser().parse("(test asdf)\n"),
             Ok(vec![Expr::Thunk(
                 vec![
-                    Expr::Atom("test".to_string(), 1..5),
-                    Expr::Atom("asdf".to_string(), 6..10),
+                    Expr::Atom("test".to_string(), sp(1..5)),
+                    Expr::Atom("asdf".to_string(), sp(6..10)),
+                ],
+                sp(0..11)
+            ),])
+        );
+        assert_eq!(
+            parser().parse("'(a)"),
+            Ok(vec![
+                Expr::Atom("quote".to_string(), sp(0..1)),
+                Expr::Thunk(vec![Expr::Atom("a".to_string(), sp(2..3))], sp(1..4)),
+            ])
+        );
+        assert_eq!(
+            parser().parse(r#"("a b" println)"#),
+            Ok(vec![Expr::Thunk(
+                vec![
+                    Expr::String("a b".to_string(), sp(1..6)),
+                    Expr::Atom("println".to_string(), sp(7..14)),
+                ],
+                sp(0..15)
+            ),])
+        );
+        assert_eq!(
+            parser().parse("[1 (a)]"),
+            Ok(vec![Expr::List(
+                vec![
+                    Expr::Integer(1, sp(1..2)),
+                    Expr::Thunk(vec![Expr::Atom("a".to_string(), sp(4..5))], sp(3..6)),
+                ],
+                sp(0..7)
+            ),])
+        );
+        assert!(parser().parse("[1 2").is_err());
+        assert!(parser().parse("[1 2)").is_err());
+        assert_eq!(
+            parser().parse("{'a 1}"),
+            Ok(vec![Expr::Map(
+                vec![
+                    Expr::Atom("quote".to_string(), sp(1..3)),
+                    Expr::Atom("a".to_string(), sp(1..3)),
+                    Expr::Integer(1, sp(4..5)),
                 ],
-                0..12
+                sp(0..6)
             ),])
         );
     }
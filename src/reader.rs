@@ -0,0 +1,325 @@
+//! A parser over `lexer` tokens that keeps open thunks, lists and maps on
+//! a heap-allocated stack rather than recursing, so nesting depth and file
+//! size are only limited by memory. It produces the same `Expr`s as
+//! `parser::parser`.
+
+use chumsky::error::{Error as _, Simple};
+
+use crate::{
+    cst::Delimiter,
+    lexer::{Lexer, Token},
+    parser::{self, Expr, FileId, Span},
+};
+
+/// A thunk, list or map that hasn't been closed yet.
+struct Frame {
+    delimiter: Delimiter,
+    open: Span,
+    /// The `'` before it, if it is quoted
+    quote: Option<Span>,
+    exprs: Vec<Expr>,
+}
+
+fn closer(d: Delimiter) -> char {
+    match d {
+        Delimiter::Paren => ')',
+        Delimiter::Bracket => ']',
+        Delimiter::Brace => '}',
+    }
+}
+
+/// Reads every expression in the source of `file`. Every error is reported,
+/// since the lexer carries on after a bad token.
+pub fn read(file: FileId, src: &str) -> Result<Vec<Expr>, Vec<Simple<char>>> {
+    match read_recovery(file, src) {
+        (exprs, errors) if errors.is_empty() => Ok(exprs),
+        (_, errors) => Err(errors),
+    }
+}
+
+/// Like `read`, but also returns whatever could be read around the errors.
+/// Bad tokens are left out and unclosed groups are dropped.
+pub fn read_recovery(file: FileId, src: &str) -> (Vec<Expr>, Vec<Simple<char>>) {
+    let mut errors = vec![];
+    let mut top = vec![];
+    let mut frames: Vec<Frame> = vec![];
+    let mut quote = None;
+
+    for t in Lexer::new(file, src) {
+        let (token, span) = match t {
+            Ok(t) => t,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+
+        let open = |delimiter, quote: &mut Option<Span>| Frame {
+            delimiter,
+            open: span.clone(),
+            quote: quote.take(),
+            exprs: vec![],
+        };
+        let expr = match token {
+            Token::Quote => {
+                quote = Some(span);
+                continue;
+            }
+            Token::LeftParen => {
+                frames.push(open(Delimiter::Paren, &mut quote));
+                continue;
+            }
+            Token::LeftBracket => {
+                frames.push(open(Delimiter::Bracket, &mut quote));
+                continue;
+            }
+            Token::LeftBrace => {
+                frames.push(open(Delimiter::Brace, &mut quote));
+                continue;
+            }
+            Token::RightParen | Token::RightBracket | Token::RightBrace => {
+                let c = match token {
+                    Token::RightParen => ')',
+                    Token::RightBracket => ']',
+                    _ => '}',
+                };
+                match frames.pop() {
+                    Some(f) if closer(f.delimiter) == c => {
+                        let span = Span::new(file, f.open.start()..span.end());
+                        let group = match f.delimiter {
+                            Delimiter::Paren => Expr::Thunk(f.exprs, span),
+                            Delimiter::Bracket => Expr::List(f.exprs, span),
+                            Delimiter::Brace => Expr::Map(f.exprs, span),
+                        };
+                        let parent = frames.last_mut().map_or(&mut top, |f| &mut f.exprs);
+                        parent.extend(f.quote.map(|q| Expr::Atom("quote".to_string(), q)));
+                        parent.push(group);
+                    }
+                    f => {
+                        let expected = f.as_ref().map(|f| closer(f.delimiter));
+                        errors.push(Simple::expected_input_found(
+                            span.range,
+                            [expected],
+                            Some(c),
+                        ));
+                        frames.extend(f);
+                    }
+                }
+                continue;
+            }
+            Token::Integer(i) => vec![Expr::Integer(i, span)],
+            Token::Float(f) => vec![Expr::Float(f, span)],
+            Token::String(s) => vec![Expr::String(s, span)],
+            Token::Char(c) => vec![Expr::Char(c, span)],
+            Token::Bytes(b) => vec![Expr::Bytes(b, span)],
+            Token::Pragma(p) => vec![Expr::Pragma(p, span)],
+            Token::Atom(m, a) => parser::expand_atom(m, &a, &span),
+        };
+        frames
+            .last_mut()
+            .map_or(&mut top, |f| &mut f.exprs)
+            .extend(expr);
+    }
+
+    if let Some(f) = frames.last() {
+        let len = src.chars().count();
+        errors.push(Simple::expected_input_found(
+            len..len,
+            [Some(closer(f.delimiter))],
+            None,
+        ));
+    }
+    (top, errors)
+}
+
+/// Whether the input so far, such as the lines typed at a prompt, is
+/// ready to run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    /// Every expression is finished
+    Complete,
+    /// A thunk, list, map, string or block comment is still open, so more
+    /// input could finish it
+    Incomplete,
+    /// No amount of further input would fix it
+    Invalid(Vec<Simple<char>>),
+}
+
+/// Reads `src` like `read_recovery`, but tells input that just stops early
+/// apart from input that is wrong.
+pub fn read_partial(file: FileId, src: &str) -> (Status, Vec<Expr>) {
+    let (exprs, errors) = read_recovery(file, src);
+    let len = src.chars().count();
+    let status = if errors.is_empty() {
+        Status::Complete
+    } else if errors
+        .iter()
+        .all(|e| e.found().is_none() && e.span().start == len)
+    {
+        Status::Incomplete
+    } else {
+        Status::Invalid(errors)
+    };
+    (status, exprs)
+}
+
+/// Collects input fed a piece at a time, such as line by line from a
+/// prompt or a socket, until it is complete or invalid.
+#[derive(Debug, Clone)]
+pub struct Incremental {
+    file: FileId,
+    buffer: String,
+    /// How many top-level expressions in the buffer were already returned
+    returned: usize,
+    /// Whether the next input starts a new buffer
+    done: bool,
+}
+
+impl Incremental {
+    pub fn new(file: FileId) -> Self {
+        Incremental {
+            file,
+            buffer: String::new(),
+            returned: 0,
+            done: false,
+        }
+    }
+
+    /// The input the last status is about, which spans point into.
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    /// Adds `input` and reads everything since the last `Complete` or
+    /// `Invalid` status again. Top-level expressions are returned as soon
+    /// as they are finished, each of them once.
+    pub fn feed(&mut self, input: &str) -> (Status, Vec<Expr>) {
+        if self.done {
+            self.buffer.clear();
+            self.returned = 0;
+        }
+        self.buffer.push_str(input);
+
+        let (status, mut exprs) = read_partial(self.file, &self.buffer);
+        self.done = status != Status::Incomplete;
+        let new = exprs.split_off(self.returned.min(exprs.len()));
+        self.returned += new.len();
+        (status, new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chumsky::Parser;
+
+    use super::*;
+    use crate::parser::parser;
+
+    #[test]
+    fn test_same_as_parser() {
+        let sources = [
+            "",
+            "  ; only a comment",
+            "#!/usr/bin/env frospy\n#frospy 1 ; c\n1 inc",
+            "#frospy 1 ext\n",
+            "(+)12 3 007 - -x 0x 0xzz 5- 0b101 -0x10",
+            "1.5 -2.0e3 1e10 #inf #-inf #nan",
+            "'a $b ^c 'x' a'b#c '<= $+",
+            "'(a) ' [ 1 ] '{'k 2} (test asdf)\n ( ) \n",
+            r#""a\"b\\c\n\t\u{3bb}" b"\x00\\\"" "(" #\( #\space #\x3bb #\λ"#,
+            "#| nested #| block |# comment |# a;b\n c\"d\"e",
+            "[1 (a [b {c d}])] {'a 1}",
+            "1 (99999999999999999999)",
+            "'12",
+            "#foo",
+            "\"unterminated",
+            "(1 2",
+            "[1 2)",
+            "1 2)",
+            "$(a)",
+            "b\"λ\"",
+            "#frospy x",
+            "#| open",
+        ];
+
+        for src in sources.iter() {
+            match (parser().parse(*src), read(FileId::default(), src)) {
+                // NaN isn't equal to itself
+                (Ok(a), Ok(b)) => assert_eq!(format!("{a:?}"), format!("{b:?}"), "{src:?}"),
+                (Err(_), Err(_)) => {}
+                (a, b) => panic!("{src:?}: parser gave {a:?} but reader gave {b:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_partial() {
+        let status = |src| read_partial(FileId::default(), src).0;
+        for src in ["", "1 2 +", "(a) ; (", "'(x)\n"] {
+            assert_eq!(status(src), Status::Complete, "{src:?}");
+        }
+        for src in [
+            "(1 2",
+            "[1 {a",
+            "\"a\\\"b",
+            "#| #| |#",
+            "1 '",
+            "(\"(\" #\\(",
+            "1 #\\",
+        ] {
+            assert_eq!(status(src), Status::Incomplete, "{src:?}");
+        }
+        for src in ["(1 2]", "1 2)", "(99999999999999999999", "(\"\\q\""] {
+            assert!(matches!(status(src), Status::Invalid(_)), "{src:?}");
+        }
+
+        let (_, exprs) = read_partial(FileId::default(), "1 (2");
+        assert_eq!(exprs, vec![Expr::Integer(1, Span::new(FileId(0), 0..1))]);
+    }
+
+    #[test]
+    fn test_incremental() {
+        let mut r = Incremental::new(FileId::default());
+        let (status, exprs) = r.feed("1 $x\n");
+        assert_eq!(status, Status::Complete);
+        assert_eq!(exprs.len(), 4);
+
+        // Finished expressions come back as soon as they are read
+        let (status, exprs) = r.feed("2 (^x\n");
+        assert_eq!(status, Status::Incomplete);
+        assert_eq!(exprs, vec![Expr::Integer(2, Span::new(FileId(0), 0..1))]);
+        let (status, exprs) = r.feed("  inc\n");
+        assert_eq!(status, Status::Incomplete);
+        assert!(exprs.is_empty());
+        let (status, exprs) = r.feed(") force\n");
+        assert_eq!(status, Status::Complete);
+        assert_eq!(exprs.len(), 2);
+        assert_eq!(exprs[0].get_span().range, 2..13);
+        assert_eq!(r.buffer(), "2 (^x\n  inc\n) force\n");
+
+        let (status, _) = r.feed("1 2]\n");
+        assert!(matches!(status, Status::Invalid(ref es) if es.len() == 1));
+        assert_eq!(r.feed("3\n").0, Status::Complete);
+        assert_eq!(r.buffer(), "3\n");
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let depth = 10_000;
+        let src = format!("{}x{}", "(".repeat(depth), ")".repeat(depth));
+        let mut e = &read(FileId::default(), &src).unwrap()[0];
+        for level in 0..depth {
+            match e {
+                Expr::Thunk(es, span) => {
+                    assert_eq!(span.range, level..2 * depth + 1 - level);
+                    e = &es[0];
+                }
+                e => panic!("expected thunk at depth {level}, got {e}"),
+            }
+        }
+        assert_eq!(
+            e,
+            &Expr::Atom("x".to_string(), Span::new(FileId(0), depth..depth + 1))
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt::Display;
+
+use crate::parser::{FileId, Span};
+
+/// A loaded source file. Spans are char offsets, so `line_starts` is
+/// too, with the byte offset each line starts at alongside.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub src: String,
+    line_starts: Vec<(usize, usize)>,
+}
+
+impl SourceFile {
+    fn new(name: String, src: String) -> Self {
+        let line_starts = std::iter::once((0, 0))
+            .chain(
+                src.char_indices()
+                    .enumerate()
+                    .filter(|(_, (_, c))| *c == '\n')
+                    .map(|(i, (byte, _))| (i + 1, byte + 1)),
+            )
+            .collect();
+        SourceFile {
+            name,
+            src,
+            line_starts,
+        }
+    }
+
+    /// 1-based line and column of a char offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|(s, _)| *s <= offset) - 1;
+        (line + 1, offset - self.line_starts[line].0 + 1)
+    }
+
+    /// The text of a 1-based line, without its line ending, or `None` past
+    /// the last line.
+    pub fn line(&self, n: usize) -> Option<&str> {
+        let (_, start) = *self.line_starts.get(n.checked_sub(1)?)?;
+        let end = match self.line_starts.get(n) {
+            Some((_, next)) => next - 1,
+            None => self.src.len(),
+        };
+        let line = &self.src[start..end];
+        Some(line.strip_suffix('\r').unwrap_or(line))
+    }
+
+    /// The source text a span covers, clamped to the end of the file.
+    pub fn slice(&self, span: &Span) -> String {
+        self.src
+            .chars()
+            .skip(span.start())
+            .take(span.end().saturating_sub(span.start()))
+            .collect()
+    }
+}
+
+/// Where a span starts, for humans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}:{}:{}", self.path, self.line, self.col))
+    }
+}
+
+/// Every file making up a program, so spans from any of them can be
+/// resolved back to their source.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a file, returning the id to parse it with.
+    pub fn add(&mut self, name: impl Into<String>, src: impl Into<String>) -> FileId {
+        self.files.push(SourceFile::new(name.into(), src.into()));
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = FileId> {
+        (0..self.files.len()).map(FileId)
+    }
+
+    pub fn get(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file.0)
+    }
+
+    pub fn location(&self, span: &Span) -> Option<Location> {
+        let file = self.get(span.file)?;
+        let (line, col) = file.line_col(span.start());
+        Some(Location {
+            path: file.name.clone(),
+            line,
+            col,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_location() {
+        let mut map = SourceMap::new();
+        let a = map.add("a.fpy", "1 2 +\nfoo");
+        let b = map.add("b.fpy", "; λ comment\n\n  bar baz");
+
+        let loc = |file, range| map.location(&Span::new(file, range)).unwrap();
+
+        assert_eq!(loc(a, 0..1).to_string(), "a.fpy:1:1");
+        assert_eq!(loc(a, 6..9).to_string(), "a.fpy:2:1");
+        assert_eq!(loc(b, 15..18).to_string(), "b.fpy:3:3");
+        assert_eq!(loc(b, 19..22).to_string(), "b.fpy:3:7");
+
+        assert_eq!(map.get(b).unwrap().slice(&Span::new(b, 15..18)), "bar");
+        assert_eq!(map.location(&Span::new(FileId(2), 0..0)), None);
+
+        let c = map.add("c.fpy", "a\r\nb\n");
+        let lines: Vec<_> = (0..5).map(|n| map.get(b).unwrap().line(n)).collect();
+        assert_eq!(
+            lines,
+            vec![None, Some("; λ comment"), Some(""), Some("  bar baz"), None]
+        );
+        let lines: Vec<_> = (1..4).map(|n| map.get(c).unwrap().line(n)).collect();
+        assert_eq!(lines, vec![Some("a"), Some("b"), Some("")]);
+    }
+}
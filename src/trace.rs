@@ -0,0 +1,167 @@
+//! Watching an evaluation as it runs. The evaluator reports what it does
+//! to an `Observer`, which is `Silent` unless something more is wanted,
+//! like the `StderrTracer` behind `frospy eval --trace`.
+
+use itertools::Itertools;
+
+use crate::eval::{EvalStacktrace, Value};
+use crate::parser::{Expr, Span};
+use crate::source_map::SourceMap;
+
+/// Hooks called by the evaluator. Every one does nothing unless
+/// overridden.
+#[allow(unused_variables)]
+pub trait Observer {
+    /// Before `e` is evaluated, `depth` frames deep, with the stack as it
+    /// is then.
+    fn instruction(&mut self, e: &Expr, depth: usize, stack: &[Value]) {}
+
+    /// `$name` bound `value`.
+    fn bind(&mut self, name: &str, value: &Value) {}
+
+    /// `^name` pushed `value`.
+    fn lookup(&mut self, name: &str, value: &Value) {}
+
+    /// The thunk forced at `span` got a frame, which replaced the caller's
+    /// if `tail`.
+    fn force(&mut self, span: &Span, tail: bool) {}
+
+    /// A frame finished. `span` is where it was forced or, for a list or
+    /// map literal, the literal, and None for the top level.
+    fn ret(&mut self, span: Option<&Span>) {}
+
+    /// The evaluation failed.
+    fn error(&mut self, e: &EvalStacktrace) {}
+}
+
+/// Ignores everything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Silent;
+
+impl Observer for Silent {}
+
+/// Prints every event to stderr, indented by how deep it happened.
+#[derive(Debug)]
+pub struct StderrTracer<'a> {
+    sources: &'a SourceMap,
+    depth: usize,
+}
+
+impl<'a> StderrTracer<'a> {
+    pub fn new(sources: &'a SourceMap) -> Self {
+        StderrTracer { sources, depth: 0 }
+    }
+
+    fn line(&self, s: &str) {
+        eprintln!(
+            "{:indent$}{s}",
+            "",
+            indent = 2 * self.depth.saturating_sub(1)
+        );
+    }
+
+    fn location(&self, span: &Span) -> String {
+        match self.sources.location(span) {
+            Some(l) => l.to_string(),
+            None => format!("{:?}", span.range),
+        }
+    }
+}
+
+impl Observer for StderrTracer<'_> {
+    fn instruction(&mut self, e: &Expr, depth: usize, stack: &[Value]) {
+        self.depth = depth;
+        // Expr's Display ignores the width
+        let e = e.to_string();
+        self.line(&format!("{e:<20} [ {} ]", stack.iter().join(" ")));
+    }
+
+    fn bind(&mut self, name: &str, value: &Value) {
+        self.line(&format!("${name} = {value}"));
+    }
+
+    fn lookup(&mut self, name: &str, value: &Value) {
+        self.line(&format!("^{name} = {value}"));
+    }
+
+    fn force(&mut self, span: &Span, tail: bool) {
+        let what = if tail { "tail call" } else { "force" };
+        self.line(&format!("{what} at {}", self.location(span)));
+    }
+
+    fn ret(&mut self, span: Option<&Span>) {
+        match span {
+            Some(s) => self.line(&format!("return from {}", self.location(s))),
+            None => self.line("return"),
+        }
+    }
+
+    fn error(&mut self, e: &EvalStacktrace) {
+        self.line(&format!("error[{}]: {}", e.error.code(), e.error));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chumsky::Parser;
+
+    use super::*;
+    use crate::eval::{eval_with, EvalOptions};
+    use crate::parser::parser;
+
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl Observer for Recorder {
+        fn bind(&mut self, name: &str, value: &Value) {
+            self.0.push(format!("bind {name} {value}"));
+        }
+
+        fn lookup(&mut self, name: &str, value: &Value) {
+            self.0.push(format!("lookup {name} {value}"));
+        }
+
+        fn force(&mut self, span: &Span, tail: bool) {
+            self.0.push(format!("force {:?} {tail}", span.range));
+        }
+
+        fn ret(&mut self, span: Option<&Span>) {
+            self.0
+                .push(format!("ret {:?}", span.map(|s| s.range.clone())));
+        }
+
+        fn error(&mut self, e: &EvalStacktrace) {
+            self.0.push(format!("error {}", e.error));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let run = |src: &str| {
+            let mut r = Recorder::default();
+            let _ = eval_with(
+                &parser().parse(src).unwrap(),
+                EvalOptions::default(),
+                &mut r,
+            );
+            r.0
+        };
+
+        assert_eq!(
+            run("1 $x (^x (2) force) force ^x"),
+            vec![
+                "bind x 1",
+                "force 20..25 false",
+                "lookup x 1",
+                "force 13..18 true",
+                "ret Some(13..18)",
+                "lookup x 1",
+                "ret None",
+            ]
+        );
+        assert_eq!(
+            run("[1 +]"),
+            vec!["error Attempted to pop from empty stack"]
+        );
+    }
+}